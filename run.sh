@@ -3,4 +3,4 @@
 set -e
 set -x
 
-RUSTFLAGS="-C target-cpu=native" cargo +nightly run --release
+RUSTFLAGS="-C target-cpu=native" cargo run --release
@@ -0,0 +1,238 @@
+//! `count_unique`, which picks a counting algorithm for the input rather than leaving the choice to the caller.
+//!
+//! A hash table wins while it fits in cache, and hashed sorting wins once it doesn't: past that point every insert
+//! into the table is a cache miss, while the sort only streams through memory a few times. The table's size depends
+//! on the number of distinct words, which is estimated from a sample.
+
+use crate::counters::{PREFETCH_DISTANCE, count_unique_in_sorted};
+use crate::distinct_counter::{CountingMethod, dense_table_bytes};
+use crate::dlsd_and_count::dlsd_sort_and_count;
+use crate::hashers::SeededMulSwapMulHasher;
+use crate::u64_hash_set::U64HashSet;
+use std::sync::OnceLock;
+
+/// Words sampled to estimate the number of distinct words.
+pub const SAMPLE_SIZE: usize = 4096;
+/// Inputs shorter than this are assumed to be all distinct rather than sampled.
+pub const MIN_SAMPLED_LEN: usize = 64 * SAMPLE_SIZE;
+/// Cache size assumed when it can't be detected.
+pub const DEFAULT_CACHE_BYTES: usize = 8 << 20;
+/// How often, in words, the hash table checks whether it has outgrown the estimate it was sized for.
+const GROWTH_CHECK_INTERVAL: usize = 4096;
+
+/// What `count_unique` chose for an input, and what it based that on.
+#[derive(Clone, Copy, Debug)]
+pub struct Decision {
+    /// `HashSet` for a `U64HashSet`, `HashedSorting` for `dlsd_sort_and_count`.
+    pub method: CountingMethod,
+    pub len: usize,
+    /// Estimated number of distinct words: from the sample if one was taken (see `MIN_SAMPLED_LEN`), `len` otherwise.
+    pub estimated_distinct: usize,
+    /// Size of the hash table `count_unique_with` would count in, sized by `table_capacity`. This is what has to fit in
+    /// cache.
+    pub table_bytes: usize,
+    pub cache_bytes: usize,
+    /// Whether the hash table outgrew the estimate, so that the count was redone with hashed sorting.
+    pub fell_back: bool,
+}
+
+/// Returns the number of distinct words in `data`, with whichever algorithm `choose` picks for it.
+pub fn count_unique(data: &[u64]) -> usize {
+    count_unique_with_decision(data).0
+}
+
+/// Like `count_unique`, but also returns the decision, e.g. for logging.
+pub fn count_unique_with_decision(data: &[u64]) -> (usize, Decision) {
+    count_unique_with(data, choose(data))
+}
+
+/// Counts with the method in `decision`, e.g. one from `choose_with_cache`. Returns the decision with `fell_back` set
+/// if the hash table outgrew its estimate.
+pub fn count_unique_with(data: &[u64], mut decision: Decision) -> (usize, Decision) {
+    if decision.method == CountingMethod::HashSet {
+        if let Some(count) = count_with_table(data, table_capacity(decision.estimated_distinct, data.len())) {
+            return (count, decision);
+        }
+        decision.fell_back = true;
+    }
+    (dlsd_sort_and_count(data, SeededMulSwapMulHasher::random()), decision)
+}
+
+/// Picks the algorithm `count_unique` uses for `data`, using the detected last-level cache size.
+pub fn choose(data: &[u64]) -> Decision {
+    choose_with_cache(data, last_level_cache_bytes())
+}
+
+/// Like `choose`, but for a cache of `cache_bytes` rather than the detected one.
+pub fn choose_with_cache(data: &[u64], cache_bytes: usize) -> Decision {
+    // Sampling costs about as much as counting a few thousand words, so smaller inputs skip it.
+    let estimated_distinct = if data.len() < MIN_SAMPLED_LEN { data.len() } else { estimate_distinct(data) };
+    let table_bytes = dense_table_bytes(table_capacity(estimated_distinct, data.len()));
+    Decision {
+        method: if table_bytes <= cache_bytes { CountingMethod::HashSet } else { CountingMethod::HashedSorting },
+        len: data.len(),
+        estimated_distinct,
+        table_bytes,
+        cache_bytes,
+        fell_back: false,
+    }
+}
+
+/// Estimates the number of distinct words in `data` from `SAMPLE_SIZE` evenly spaced words.
+///
+/// Uses the birthday bound: with `d` equally frequent distinct words, a sample of `s` words has about `s^2 / 2d`
+/// equal pairs. With no equal pairs, the sample only says there are more than about `s^2 / 2` distinct words, and
+/// `data.len()` is returned.
+pub fn estimate_distinct(data: &[u64]) -> usize {
+    if data.len() <= SAMPLE_SIZE {
+        let mut sample = data.to_vec();
+        sample.sort_unstable();
+        return count_unique_in_sorted(&sample);
+    }
+    let stride = data.len() / SAMPLE_SIZE;
+    let mut sample: Vec<u64> = data.iter().step_by(stride).take(SAMPLE_SIZE).copied().collect();
+    sample.sort_unstable();
+    let mut equal_pairs = 0;
+    for run in sample.chunk_by(|a, b| a == b) {
+        equal_pairs += run.len() * (run.len() - 1) / 2;
+    }
+    if equal_pairs == 0 {
+        return data.len();
+    }
+    let estimate = SAMPLE_SIZE * (SAMPLE_SIZE - 1) / (2 * equal_pairs);
+    estimate.clamp(count_unique_in_sorted(&sample), data.len())
+}
+
+/// Returns the capacity of the hash table for an input of `len` words with about `estimated_distinct` distinct ones.
+///
+/// Leaves headroom over the estimate, which sampling can get wrong, e.g. on skewed data. At least
+/// `GROWTH_CHECK_INTERVAL`, so that `count_with_table` checks before the table grows.
+fn table_capacity(estimated_distinct: usize, len: usize) -> usize {
+    (2 * estimated_distinct).max(GROWTH_CHECK_INTERVAL).min(len)
+}
+
+/// Counts with a `U64HashSet` sized for `capacity` words, or returns `None` if there turn out to be more.
+///
+/// `U64HashSet` would grow to hold them, but then it may no longer fit in the cache it was chosen for. It holds
+/// `capacity` words without growing, so checking every `GROWTH_CHECK_INTERVAL` words, which is no more than
+/// `capacity`, stops before it has grown more than once.
+fn count_with_table(data: &[u64], capacity: usize) -> Option<usize> {
+    let mut set = U64HashSet::with_capacity_and_hasher(capacity, SeededMulSwapMulHasher::random());
+    for (i, &d) in data.iter().enumerate() {
+        if let Some(&prefetch_d) = data.get(i + PREFETCH_DISTANCE) {
+            set.prefetch(prefetch_d);
+        }
+        set.insert(d);
+        if i % GROWTH_CHECK_INTERVAL == GROWTH_CHECK_INTERVAL - 1 && set.len() > capacity {
+            return None;
+        }
+    }
+    Some(set.len())
+}
+
+/// Returns the size of the largest cache, or `DEFAULT_CACHE_BYTES` if it can't be detected. Detected once.
+pub fn last_level_cache_bytes() -> usize {
+    static CACHE_BYTES: OnceLock<usize> = OnceLock::new();
+    *CACHE_BYTES
+        .get_or_init(|| cache_sizes().into_iter().map(|(_, bytes)| bytes).max().unwrap_or(DEFAULT_CACHE_BYTES))
+}
+
+/// Data and unified cache sizes of the first CPU, as (name, bytes), e.g. ("L1d", 49152). Empty if not available (only
+/// implemented for Linux).
+pub fn cache_sizes() -> Vec<(String, usize)> {
+    let mut caches = Vec::new();
+    for index in 0.. {
+        let dir = format!("/sys/devices/system/cpu/cpu0/cache/index{}", index);
+        let read = |file: &str| std::fs::read_to_string(format!("{}/{}", dir, file)).map(|s| s.trim().to_string());
+        let (Ok(level), Ok(kind), Ok(size)) = (read("level"), read("type"), read("size")) else {
+            break;
+        };
+        let suffix = match kind.as_str() {
+            "Data" => "d",
+            "Unified" => "",
+            _ => continue,
+        };
+        let bytes = match size.strip_suffix('K') {
+            Some(kib) => kib.parse::<usize>().ok().map(|kib| kib * 1024),
+            None => size.parse().ok(),
+        };
+        if let Some(bytes) = bytes {
+            caches.push((format!("L{}{}", level, suffix), bytes));
+        }
+    }
+    caches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEN: usize = 1 << 20;
+
+    fn uniform(distinct: u64) -> Vec<u64> {
+        let mut rng = fastrand::Rng::with_seed(1);
+        (0..LEN).map(|_| rng.u64(..distinct)).collect()
+    }
+
+    fn exact_count(data: &[u64]) -> usize {
+        let mut sorted = data.to_vec();
+        sorted.sort_unstable();
+        count_unique_in_sorted(&sorted)
+    }
+
+    #[test]
+    fn uniform_input() {
+        let data = uniform(50_000);
+        let estimate = estimate_distinct(&data);
+        assert!(estimate.abs_diff(50_000) < 5_000, "{estimate}");
+        // Room for twice the estimate, rounded up to 2^17 words of 16 bytes.
+        let table_bytes = 2 << 20;
+        let fits = choose_with_cache(&data, table_bytes);
+        assert_eq!((fits.method, fits.table_bytes), (CountingMethod::HashSet, table_bytes));
+        let spills = choose_with_cache(&data, table_bytes - 1);
+        assert_eq!(spills.method, CountingMethod::HashedSorting);
+        for decision in [fits, spills] {
+            let (count, decision) = count_unique_with(&data, decision);
+            assert_eq!(count, exact_count(&data));
+            assert!(!decision.fell_back);
+        }
+    }
+
+    #[test]
+    fn all_equal_input() {
+        let data = vec![7; LEN];
+        assert_eq!(estimate_distinct(&data), 1);
+        let decision = choose_with_cache(&data, DEFAULT_CACHE_BYTES);
+        // Sized for `GROWTH_CHECK_INTERVAL` words rather than twice the estimate.
+        assert_eq!(decision.table_bytes, dense_table_bytes(GROWTH_CHECK_INTERVAL));
+        assert_eq!(count_unique_with(&data, decision).0, 1);
+    }
+
+    #[test]
+    fn skewed_input() {
+        // About half the words are 0 and the rest distinct, so the sample's pairs of zeros hide the distinct words.
+        let mut rng = fastrand::Rng::with_seed(2);
+        let data: Vec<u64> = (0..LEN).map(|_| if rng.bool() { 0 } else { rng.u64(..) }).collect();
+        let estimate = estimate_distinct(&data);
+        // Still no less than the number of distinct words in the sample.
+        assert!((SAMPLE_SIZE / 2..LEN / 8).contains(&estimate), "{estimate}");
+        let decision = choose_with_cache(&data, DEFAULT_CACHE_BYTES);
+        assert_eq!(decision.method, CountingMethod::HashSet);
+        let (count, decision) = count_unique_with(&data, decision);
+        assert_eq!(count, exact_count(&data));
+        assert!(decision.fell_back);
+    }
+
+    #[test]
+    fn tiny_input() {
+        assert_eq!(estimate_distinct(&[3, 1, 3, 2]), 3);
+        let data: Vec<u64> = (0..100).collect();
+        // Too short to sample, so assumed all distinct, with a table for no more than all of them.
+        let decision = choose_with_cache(&data, DEFAULT_CACHE_BYTES);
+        assert_eq!(decision.estimated_distinct, 100);
+        assert_eq!(decision.table_bytes, dense_table_bytes(100));
+        assert_eq!(count_unique_with(&data, decision).0, 100);
+        let empty = choose_with_cache(&[], DEFAULT_CACHE_BYTES);
+        assert_eq!(count_unique_with(&[], empty).0, 0);
+    }
+}
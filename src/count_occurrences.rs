@@ -0,0 +1,100 @@
+//! Counting how many times each distinct word of a slice occurs, as (word, count) pairs.
+//!
+//! The order of the pairs depends on the algorithm: sorted for the sort, table order for the hash map, and hash order
+//! (roughly, for the fused sort) for the hashed sorts. The hashed sorts count runs of equal hashes, then map them back
+//! to words with an `InvertibleU64Hasher`, so they are exact.
+
+use crate::counters::PREFETCH_DISTANCE;
+use crate::dlsd::dlsd_sort;
+use crate::dlsd_and_count::dlsd_sort_and_count_occurrences;
+use crate::hashers::{InvertibleU64Hasher, SeededU64Hasher};
+use crate::u64_count_map::U64CountMap;
+
+/// Returns each distinct word of `data` with its count, sorted by word, with `sort_unstable` and run-length
+/// counting. The baseline for the others.
+pub fn count_occurrences_by_sort(data: &[u64]) -> Vec<(u64, usize)> {
+    let mut sorted_data = data.to_vec();
+    sorted_data.sort_unstable();
+    run_lengths(&sorted_data)
+}
+
+/// Returns each distinct word of `data` with its count, in table order, by adding them to `map`, which is cleared
+/// first.
+pub fn count_occurrences_by_u64_hash<H: SeededU64Hasher>(data: &[u64], map: &mut U64CountMap<H>) -> Vec<(u64, usize)> {
+    map.clear();
+    let (head, tail) = data.split_at(data.len().saturating_sub(PREFETCH_DISTANCE));
+    for (&d, &prefetch_d) in head.iter().zip(&data[PREFETCH_DISTANCE.min(data.len())..]) {
+        map.prefetch(prefetch_d);
+        map.add(d);
+    }
+    for &d in tail {
+        map.add(d);
+    }
+    let mut occurrences = Vec::with_capacity(map.len());
+    occurrences.extend(map.iter());
+    occurrences
+}
+
+/// Returns each distinct word of `data` with its count, in hash order, by sorting the hashes with `dlsd_sort`,
+/// counting runs, and unhashing.
+pub fn count_occurrences_by_hashed_dlsd_sort<H: InvertibleU64Hasher>(data: &[u64], hasher: H) -> Vec<(u64, usize)> {
+    let mut occurrences = run_lengths(&dlsd_sort(data, hasher));
+    unhash_words(&mut occurrences, hasher);
+    occurrences
+}
+
+/// Like `count_occurrences_by_hashed_dlsd_sort`, but with `dlsd_sort_and_count_occurrences`, which counts runs during
+/// its last pass.
+pub fn count_occurrences_by_fused_hashed_dlsd_sort<H: InvertibleU64Hasher>(
+    data: &[u64],
+    hasher: H,
+) -> Vec<(u64, usize)> {
+    let mut occurrences = dlsd_sort_and_count_occurrences(data, hasher);
+    unhash_words(&mut occurrences, hasher);
+    occurrences
+}
+
+/// Returns each run of equal words in `sorted_data` with its length.
+pub fn run_lengths(sorted_data: &[u64]) -> Vec<(u64, usize)> {
+    sorted_data.chunk_by(|a, b| a == b).map(|run| (run[0], run.len())).collect()
+}
+
+/// Maps the hashes in `occurrences` back to their words.
+fn unhash_words<H: InvertibleU64Hasher>(occurrences: &mut [(u64, usize)], hasher: H) {
+    for (word, _) in occurrences {
+        *word = hasher.unhash(*word);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashers::{MulSwapMulHasher, MurmurHasher, SeededMulSwapMulHasher};
+    use crate::test_inputs::{SEEDS, inputs};
+
+    #[test]
+    fn strategies_match_run_lengths() {
+        let mut map = U64CountMap::<MurmurHasher>::with_capacity(16);
+        for (data, seed) in inputs().into_iter().zip(SEEDS.into_iter().cycle()) {
+            let mut sorted_data = data.clone();
+            sorted_data.sort_unstable();
+            let expected = run_lengths(&sorted_data);
+            let len = data.len();
+
+            // Only the sort's pairs come out sorted; the others are compared as multisets.
+            let sorted = |mut occurrences: Vec<(u64, usize)>| {
+                occurrences.sort_unstable();
+                occurrences
+            };
+            assert_eq!(count_occurrences_by_sort(&data), expected, "sort, len {len}");
+            assert_eq!(sorted(count_occurrences_by_u64_hash(&data, &mut map)), expected, "u64_hash, len {len}");
+            let hasher = SeededMulSwapMulHasher::with_seed(seed);
+            assert_eq!(sorted(count_occurrences_by_hashed_dlsd_sort(&data, hasher)), expected, "dlsd, len {len}");
+            assert_eq!(
+                sorted(count_occurrences_by_fused_hashed_dlsd_sort(&data, MulSwapMulHasher)),
+                expected,
+                "fused dlsd, len {len}"
+            );
+        }
+    }
+}
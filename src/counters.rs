@@ -0,0 +1,322 @@
+//! Counting the unique words of a slice, with every algorithm in this crate: hash sets, sorts, and hashed sorts.
+//!
+//! The hashed sorts sort hashes rather than the words themselves, so they count distinct hashes. That is the exact
+//! count with a hasher that is a bijection on u64, which is every hasher in `hashers` but `WyHasher` and `AesHasher`.
+//!
+//! Functions taking a `Scratch` sort into its buffers, so reusing one `Scratch` across calls avoids allocating.
+
+use crate::dlsd::dlsd_sort_with_scratch;
+use crate::dlsd_and_count::{dlsd_sort_and_count_lean_with_scratch, dlsd_sort_and_count_with_scratch};
+use crate::external_sort::external_count_unique;
+use crate::hashers::SeededU64Hasher;
+use crate::msd::{msd_sort_and_count_with_scratch, msd_sort_with_scratch};
+use crate::u64_hash_set::U64HashSet;
+use crate::uninit::{grow, hash_batch_into};
+use crate::wide_merge_sort::{
+    FanIn, SupportedFanIn, par_wide_merge_sort_with_scratch, wide_merge_sort_and_count_with_scratch,
+    wide_merge_sort_with_fan_in,
+};
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::hash::BuildHasher;
+use std::io;
+use std::mem::MaybeUninit;
+use voracious_radix_sort::RadixSort;
+
+/// How many words ahead `count_unique_by_u64_hash` prefetches their buckets.
+pub const PREFETCH_DISTANCE: usize = 64;
+/// Words per parallel task when hashing with `hash_batch`.
+pub const PARALLEL_HASH_BLOCK: usize = 1 << 14;
+
+/// Caller-owned scratch buffers for the sorting algorithms, reusable across calls.
+///
+/// Buffers grow on demand and are never shrunk, so after the first call for a given input size no further allocation
+/// (or page faulting) happens. They are never initialized: the sorts write each word before reading it.
+#[derive(Default)]
+pub struct Scratch {
+    data: Vec<MaybeUninit<u64>>,
+    aux: Vec<MaybeUninit<u64>>,
+}
+
+impl Scratch {
+    /// Returns two possibly uninitialized buffers of length `len`.
+    fn buffers(&mut self, len: usize) -> (&mut [MaybeUninit<u64>], &mut [MaybeUninit<u64>]) {
+        grow(&mut self.data, len);
+        grow(&mut self.aux, len);
+        (&mut self.data[..len], &mut self.aux[..len])
+    }
+
+    /// Returns one possibly uninitialized buffer of length `len`.
+    fn buffer(&mut self, len: usize) -> &mut [MaybeUninit<u64>] {
+        grow(&mut self.data, len);
+        &mut self.data[..len]
+    }
+
+    /// Returns a possibly uninitialized buffer of length `len`, and the other buffer whole, for
+    /// `dlsd_sort_and_count_lean_with_scratch` to grow as it needs.
+    fn lean_buffers(&mut self, len: usize) -> (&mut [MaybeUninit<u64>], &mut Vec<MaybeUninit<u64>>) {
+        grow(&mut self.data, len);
+        (&mut self.data[..len], &mut self.aux)
+    }
+}
+
+/// Counts with a std `HashSet`, which is cleared first.
+pub fn count_unique_by_hash<Hasher: BuildHasher>(data: &[u64], set: &mut HashSet<u64, Hasher>) -> usize {
+    set.clear();
+    for d in data {
+        set.insert(*d);
+    }
+    set.len()
+}
+
+/// Counts with a `U64HashSet`, which is cleared first, prefetching each word's bucket `PREFETCH_DISTANCE` words
+/// ahead.
+pub fn count_unique_by_u64_hash<H: SeededU64Hasher>(data: &[u64], set: &mut U64HashSet<H>) -> usize {
+    set.clear();
+    let (head, tail) = data.split_at(data.len().saturating_sub(PREFETCH_DISTANCE));
+    for (&d, &prefetch_d) in head.iter().zip(&data[PREFETCH_DISTANCE.min(data.len())..]) {
+        set.prefetch(prefetch_d);
+        set.insert(d);
+    }
+    for &d in tail {
+        set.insert(d);
+    }
+    set.len()
+}
+
+/// Counts by copying `data` into `scratch`, sorting it with `sort_fn`, and counting runs.
+pub fn count_unique_by_sort<F>(data: &[u64], scratch: &mut Scratch, sort_fn: F) -> usize
+where
+    F: FnOnce(&mut [u64]),
+{
+    let sorted_data = scratch.buffer(data.len()).write_copy_of_slice(data);
+    sort_fn(sorted_data);
+    count_unique_in_sorted(sorted_data)
+}
+
+/// Counts by sorting with `wide_merge_sort` at fan-in `F`.
+pub fn count_unique_by_wide_merge_sort<const F: usize>(data: &[u64], scratch: &mut Scratch) -> usize
+where
+    FanIn<F>: SupportedFanIn,
+{
+    let (sorted_data, aux) = scratch.buffers(data.len());
+    let sorted_data = sorted_data.write_copy_of_slice(data);
+    wide_merge_sort_with_fan_in::<F>(sorted_data, aux);
+    count_unique_in_sorted(sorted_data)
+}
+
+/// Counts with `wide_merge_sort_and_count`, which counts during its final merge.
+pub fn count_unique_by_fused_wide_merge_sort(data: &[u64], scratch: &mut Scratch) -> usize {
+    let (sorted_data, aux) = scratch.buffers(data.len());
+    wide_merge_sort_and_count_with_scratch(sorted_data.write_copy_of_slice(data), aux)
+}
+
+/// Counts with `external_count_unique`, holding about `memory_budget` bytes in memory at a time.
+pub fn count_unique_by_external_sort(data: &[u64], memory_budget: usize) -> io::Result<usize> {
+    external_count_unique(data.iter().copied(), memory_budget)
+}
+
+/// Counts by hashing `data` into `scratch`, then sorting the hashes with a radix sort.
+pub fn count_unique_by_hashed_sort<H: SeededU64Hasher>(data: &[u64], hasher: H, scratch: &mut Scratch) -> usize {
+    let hashed_data = hash_batch_into(hasher, data, scratch.buffer(data.len()));
+    hashed_data.voracious_sort();
+    count_unique_in_sorted(hashed_data)
+}
+
+/// Counts by sorting the hashes with `dlsd_sort`.
+pub fn count_unique_by_hashed_dlsd_sort<H: SeededU64Hasher>(data: &[u64], hasher: H, scratch: &mut Scratch) -> usize {
+    let (sorted_data, aux) = scratch.buffers(data.len());
+    count_unique_in_sorted(dlsd_sort_with_scratch(data, hasher, sorted_data, aux))
+}
+
+/// Counts with `dlsd_sort_and_count`, which counts as it sorts.
+pub fn count_unique_by_fused_hashed_dlsd_sort<H: SeededU64Hasher>(
+    data: &[u64],
+    hasher: H,
+    scratch: &mut Scratch,
+) -> usize {
+    let (hashed_data, aux) = scratch.buffers(data.len());
+    dlsd_sort_and_count_with_scratch(data, hasher, hashed_data, aux)
+}
+
+/// Counts with `dlsd_sort_and_count_lean`, whose second buffer only grows to the largest bucket.
+pub fn count_unique_by_fused_lean_hashed_dlsd_sort<H: SeededU64Hasher>(
+    data: &[u64],
+    hasher: H,
+    scratch: &mut Scratch,
+) -> usize {
+    let (hashed_data, aux) = scratch.lean_buffers(data.len());
+    dlsd_sort_and_count_lean_with_scratch(data, hasher, hashed_data, aux)
+}
+
+/// Counts by sorting the hashes with `msd_sort`.
+pub fn count_unique_by_hashed_msd_sort<H: SeededU64Hasher>(data: &[u64], hasher: H, scratch: &mut Scratch) -> usize {
+    count_unique_in_sorted(msd_sort_with_scratch(data, hasher, scratch.buffer(data.len())))
+}
+
+/// Counts with `msd_sort_and_count`, which counts as it sorts.
+pub fn count_unique_by_fused_hashed_msd_sort<H: SeededU64Hasher>(
+    data: &[u64],
+    hasher: H,
+    scratch: &mut Scratch,
+) -> usize {
+    msd_sort_and_count_with_scratch(data, hasher, scratch.buffer(data.len()))
+}
+
+/// Counts by copying `data` into `sorted_data` in parallel, sorting it with `sort_fn`, and counting runs in parallel.
+pub fn count_unique_by_parallel_sort<F>(data: &[u64], sorted_data: &mut Vec<u64>, sort_fn: F) -> usize
+where
+    F: FnOnce(&mut Vec<u64>),
+{
+    data.par_iter().copied().collect_into_vec(sorted_data);
+    sort_fn(sorted_data);
+    count_unique_in_sorted_parallel(sorted_data)
+}
+
+/// Counts by sorting with `par_wide_merge_sort`.
+pub fn count_unique_by_parallel_wide_merge_sort(data: &[u64], scratch: &mut Scratch) -> usize {
+    let (sorted_data, aux) = scratch.buffers(data.len());
+    sorted_data.par_iter_mut().zip(data).for_each(|(dst, &src)| {
+        dst.write(src);
+    });
+    // Safety: every word was just written.
+    let sorted_data = unsafe { sorted_data.assume_init_mut() };
+    par_wide_merge_sort_with_scratch(sorted_data, aux);
+    count_unique_in_sorted_parallel(sorted_data)
+}
+
+/// Counts by hashing `data` into `sorted_data` in parallel, sorting the hashes with `sort_fn`, and counting runs in
+/// parallel.
+pub fn count_unique_by_hashed_parallel_sort<H: SeededU64Hasher + Sync>(
+    data: &[u64],
+    hasher: H,
+    sorted_data: &mut Vec<u64>,
+    sort_fn: impl FnOnce(&mut Vec<u64>),
+) -> usize {
+    sorted_data.resize(data.len(), 0);
+    sorted_data
+        .par_chunks_mut(PARALLEL_HASH_BLOCK)
+        .zip(data.par_chunks(PARALLEL_HASH_BLOCK))
+        .for_each(|(hashes, values)| hasher.hash_batch(values, hashes));
+    sort_fn(sorted_data);
+    count_unique_in_sorted_parallel(sorted_data)
+}
+
+/// Counts the runs of equal words in `sorted_data`.
+pub fn count_unique_in_sorted(sorted_data: &[u64]) -> usize {
+    count_unique_in_sorted_by_key(sorted_data, |&word| word)
+}
+
+/// Counts the runs of equal keys in `sorted_data`, which is sorted by `key`.
+pub fn count_unique_in_sorted_by_key<T>(sorted_data: &[T], key: impl Fn(&T) -> u64) -> usize {
+    let Some(first) = sorted_data.first() else {
+        return 0;
+    };
+
+    let mut count = 1;
+    let mut prev = key(first);
+
+    for current in &sorted_data[1..] {
+        let current = key(current);
+        count += (current != prev) as usize;
+        prev = current;
+    }
+
+    count
+}
+
+/// Like `count_unique_in_sorted`, but in parallel.
+pub fn count_unique_in_sorted_parallel(sorted_data: &[u64]) -> usize {
+    if sorted_data.is_empty() {
+        return 0;
+    }
+    1 + sorted_data
+        .par_windows(2)
+        .map(|w| (w[0] != w[1]) as usize)
+        .sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dlsd::dlsd_sort;
+    use crate::dlsd_and_count::{
+        dlsd_sort_and_count, dlsd_sort_and_count_lean, dlsd_sort_and_count_occurrences,
+        dlsd_sort_and_count_occurrences_with_scratch,
+    };
+    use crate::alloc_count::allocations_during;
+    use crate::hashers::MulSwapMulHasher;
+    use crate::msd::{msd_sort, msd_sort_and_count};
+
+    #[test]
+    fn scratch_can_be_reused() {
+        let h = MulSwapMulHasher;
+        let mut rng = fastrand::Rng::with_seed(1);
+        let mut scratch = Scratch::default();
+        let mut occurrences = Vec::new();
+        // Larger input first, so that every buffer of the second call still holds words from the first.
+        for (len, domain) in [(100_000, u64::MAX), (30_000, 1000)] {
+            let data: Vec<u64> = (0..len).map(|_| rng.u64(..domain)).collect();
+
+            let (sorted, aux) = scratch.buffers(len);
+            assert_eq!(dlsd_sort_with_scratch(&data, h, sorted, aux), dlsd_sort(&data, h));
+            let (hashed, aux) = scratch.buffers(len);
+            assert_eq!(dlsd_sort_and_count_with_scratch(&data, h, hashed, aux), dlsd_sort_and_count(&data, h));
+            let (hashed, aux) = scratch.lean_buffers(len);
+            let lean_count = dlsd_sort_and_count_lean_with_scratch(&data, h, hashed, aux);
+            assert_eq!(lean_count, dlsd_sort_and_count_lean(&data, h));
+            let (hashed, aux) = scratch.buffers(len);
+            dlsd_sort_and_count_occurrences_with_scratch(&data, h, hashed, aux, &mut occurrences);
+            let mut expected_occurrences = dlsd_sort_and_count_occurrences(&data, h);
+            occurrences.sort_unstable();
+            expected_occurrences.sort_unstable();
+            assert_eq!(occurrences, expected_occurrences);
+            assert_eq!(msd_sort_with_scratch(&data, h, scratch.buffer(len)), msd_sort(&data, h));
+            assert_eq!(msd_sort_and_count_with_scratch(&data, h, scratch.buffer(len)), msd_sort_and_count(&data, h));
+
+            let mut sorted = data.clone();
+            sorted.sort_unstable();
+            let expected = count_unique_in_sorted(&sorted);
+            let counts = [
+                count_unique_by_sort(&data, &mut scratch, |v| v.sort_unstable()),
+                count_unique_by_wide_merge_sort::<256>(&data, &mut scratch),
+                count_unique_by_fused_wide_merge_sort(&data, &mut scratch),
+                count_unique_by_hashed_sort(&data, h, &mut scratch),
+                count_unique_by_hashed_dlsd_sort(&data, h, &mut scratch),
+                count_unique_by_fused_hashed_dlsd_sort(&data, h, &mut scratch),
+                count_unique_by_fused_lean_hashed_dlsd_sort(&data, h, &mut scratch),
+                count_unique_by_hashed_msd_sort(&data, h, &mut scratch),
+                count_unique_by_fused_hashed_msd_sort(&data, h, &mut scratch),
+            ];
+            assert_eq!(counts, [expected; 9], "len {len}");
+        }
+    }
+
+    #[test]
+    fn reused_scratch_does_not_allocate() {
+        let mut rng = fastrand::Rng::with_seed(2);
+        let mut scratch = Scratch::default();
+        // Large enough for the lean version's buckets to go through its radix passes rather than `sort_unstable`.
+        let data: Vec<u64> = (0..1 << 20).map(|_| rng.u64(..)).collect();
+        type Count = fn(&[u64], &mut Scratch) -> usize;
+        let counts: [(&str, Count); 7] = [
+            ("wide_merge_sort", |data, scratch| count_unique_by_wide_merge_sort::<256>(data, scratch)),
+            ("fused wide_merge_sort", count_unique_by_fused_wide_merge_sort),
+            ("dlsd", |data, scratch| count_unique_by_hashed_dlsd_sort(data, MulSwapMulHasher, scratch)),
+            ("fused dlsd", |data, scratch| count_unique_by_fused_hashed_dlsd_sort(data, MulSwapMulHasher, scratch)),
+            ("fused lean dlsd", |data, scratch| {
+                count_unique_by_fused_lean_hashed_dlsd_sort(data, MulSwapMulHasher, scratch)
+            }),
+            ("msd", |data, scratch| count_unique_by_hashed_msd_sort(data, MulSwapMulHasher, scratch)),
+            ("fused msd", |data, scratch| count_unique_by_fused_hashed_msd_sort(data, MulSwapMulHasher, scratch)),
+        ];
+        for (name, count) in counts {
+            // The first call grows the scratch; the second must find it big enough.
+            count(&data, &mut scratch);
+            let allocations = allocations_during(|| {
+                count(&data, &mut scratch);
+            });
+            assert_eq!(allocations, 0, "{name}");
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Deduplicating a slice: returning its distinct words, rather than just counting them as `counters` does.
+//!
+//! The order of the result depends on the algorithm: sorted for the sorts, table order for the hash set, and hash
+//! order for the hashed sorts. The hashed sorts deduplicate hashes, then map them back to words with an
+//! `InvertibleU64Hasher`, so they are exact.
+//!
+//! The `dedup_stable_*` functions instead keep the words in the order they first appear, as when deduplicating a
+//! stream of events.
+
+use crate::counters::{PREFETCH_DISTANCE, count_unique_by_u64_hash};
+use crate::dlsd::dlsd_sort;
+use crate::hashers::{InvertibleU64Hasher, SeededU64Hasher};
+use crate::msd::{msd_sort, sort_hashed_in_place};
+use crate::u64_hash_set::U64HashSet;
+use voracious_radix_sort::RadixSort;
+
+/// Returns the distinct words of `data`, sorted, with `sort_unstable` and `Vec::dedup`. The baseline for the others.
+pub fn dedup_unique_by_sort_and_dedup(data: &[u64]) -> Vec<u64> {
+    let mut sorted_data = data.to_vec();
+    sorted_data.sort_unstable();
+    sorted_data.dedup();
+    sorted_data
+}
+
+/// Returns the distinct words of `data` in table order, by inserting them into `set`, which is cleared first.
+pub fn dedup_unique_by_u64_hash<H: SeededU64Hasher>(data: &[u64], set: &mut U64HashSet<H>) -> Vec<u64> {
+    let mut unique = Vec::with_capacity(count_unique_by_u64_hash(data, set));
+    unique.extend(set.iter());
+    unique
+}
+
+/// Returns the distinct words of `data`, sorted, by copying it, sorting it with `sort_fn`, and compacting it in place.
+pub fn dedup_unique_by_sort<F>(data: &[u64], sort_fn: F) -> Vec<u64>
+where
+    F: FnOnce(&mut [u64]),
+{
+    let mut sorted_data = data.to_vec();
+    sort_fn(&mut sorted_data);
+    let len = dedup_sorted(&mut sorted_data);
+    sorted_data.truncate(len);
+    sorted_data
+}
+
+/// Returns the distinct words of `data` in hash order, by hashing it, sorting the hashes with a radix sort,
+/// compacting them, and unhashing what is left.
+pub fn dedup_unique_by_hashed_sort<H: InvertibleU64Hasher>(data: &[u64], hasher: H) -> Vec<u64> {
+    let mut hashed_data = vec![0u64; data.len()];
+    hasher.hash_batch(data, &mut hashed_data);
+    hashed_data.voracious_sort();
+    unhash_sorted(hashed_data, hasher)
+}
+
+/// Like `dedup_unique_by_hashed_sort`, but sorting the hashes with `dlsd_sort`.
+pub fn dedup_unique_by_hashed_dlsd_sort<H: InvertibleU64Hasher>(data: &[u64], hasher: H) -> Vec<u64> {
+    unhash_sorted(dlsd_sort(data, hasher), hasher)
+}
+
+/// Like `dedup_unique_by_hashed_sort`, but sorting the hashes with `msd_sort`.
+pub fn dedup_unique_by_hashed_msd_sort<H: InvertibleU64Hasher>(data: &[u64], hasher: H) -> Vec<u64> {
+    unhash_sorted(msd_sort(data, hasher), hasher)
+}
+
+/// Returns the distinct words of `data` in the order they first appear, by keeping the words `set` didn't have yet.
+/// `set` is cleared first.
+pub fn dedup_stable_by_u64_hash<H: SeededU64Hasher>(data: &[u64], set: &mut U64HashSet<H>) -> Vec<u64> {
+    set.clear();
+    let mut unique = Vec::new();
+    for (i, &d) in data.iter().enumerate() {
+        if let Some(&prefetch_d) = data.get(i + PREFETCH_DISTANCE) {
+            set.prefetch(prefetch_d);
+        }
+        if set.insert(d) {
+            unique.push(d);
+        }
+    }
+    unique
+}
+
+/// Groups of packed words with equal hash bits up to this size are deduplicated by scanning, and larger ones by sorting.
+const SMALL_GROUP: usize = 16;
+
+/// Returns the distinct words of `data` in the order they first appear, by sorting words that pack each word's hash
+/// with its index, marking the first index of each word, and keeping the marked words.
+///
+/// The hash goes in the high bits, which groups equal words together, and the index in the low bits, which orders each
+/// group by position. The packed words are sorted in place with `msd_sort`'s radix sort. The index replaces the low
+/// bits of the hash, so distinct words can share a group. A weak hasher on dense keys can put nearly all of them in
+/// one, so groups larger than `SMALL_GROUP` are sorted by (word, index) rather than scanned, which keeps the work
+/// O(g log g) per group of g words.
+///
+/// Uses 8 bytes per word for the packed words and a bit per word for the marks, besides the result, plus 16 bytes per
+/// word of the largest group larger than `SMALL_GROUP`.
+pub fn dedup_stable_by_hashed_sort<H: SeededU64Hasher>(data: &[u64], hasher: H) -> Vec<u64> {
+    let index_bits = usize::BITS - data.len().saturating_sub(1).leading_zeros();
+    let index_mask = u64::MAX.checked_shr(u64::BITS - index_bits).unwrap_or(0);
+    let mut packed = vec![0u64; data.len()];
+    hasher.hash_batch(data, &mut packed);
+    for (i, word) in packed.iter_mut().enumerate() {
+        *word = (*word & !index_mask) | i as u64;
+    }
+    sort_hashed_in_place(&mut packed);
+
+    let mut is_first = vec![0u64; data.len().div_ceil(64)];
+    let mut mark = |i: usize| is_first[i / 64] |= 1 << (i % 64);
+    let mut group_words = Vec::new();
+    let mut group_entries = Vec::new();
+    for group in packed.chunk_by(|a, b| (a ^ b) & !index_mask == 0) {
+        // Most groups hold a single occurrence of a single word, which needs no lookup in `data`.
+        if let &[word] = group {
+            mark((word & index_mask) as usize);
+            continue;
+        }
+        if group.len() <= SMALL_GROUP {
+            group_words.clear();
+            for &word in group {
+                let i = (word & index_mask) as usize;
+                if !group_words.contains(&data[i]) {
+                    group_words.push(data[i]);
+                    mark(i);
+                }
+            }
+            continue;
+        }
+        // Sorting by (word, index) puts each word's first occurrence at the start of its run.
+        group_entries.clear();
+        group_entries.extend(group.iter().map(|&word| {
+            let i = (word & index_mask) as usize;
+            (data[i], i)
+        }));
+        group_entries.sort_unstable();
+        for run in group_entries.chunk_by(|a, b| a.0 == b.0) {
+            mark(run[0].1);
+        }
+    }
+    drop(packed);
+    data.iter().enumerate().filter(|&(i, _)| is_first[i / 64] >> (i % 64) & 1 != 0).map(|(_, &d)| d).collect()
+}
+
+/// Moves the distinct words of `sorted_data` to its front, in order, and returns how many there are. The rest of
+/// `sorted_data` is left with unspecified contents.
+pub fn dedup_sorted(sorted_data: &mut [u64]) -> usize {
+    if sorted_data.is_empty() {
+        return 0;
+    }
+    let mut len = 1;
+    for i in 1..sorted_data.len() {
+        // Branchless: always write the word, but only keep it if it differs from the last one kept.
+        let current = sorted_data[i];
+        let is_new = current != sorted_data[len - 1];
+        sorted_data[len] = current;
+        len += is_new as usize;
+    }
+    len
+}
+
+/// Deduplicates the sorted hashes in `hashes` and maps them back to their words.
+fn unhash_sorted<H: InvertibleU64Hasher>(mut hashes: Vec<u64>, hasher: H) -> Vec<u64> {
+    let len = dedup_sorted(&mut hashes);
+    hashes.truncate(len);
+    for hash in &mut hashes {
+        *hash = hasher.unhash(*hash);
+    }
+    hashes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashers::{MulSwapMulHasher, MurmurHasher, NoopHasher, SeededMulSwapMulHasher};
+    use crate::test_inputs::{SEEDS, inputs};
+    use std::collections::HashSet;
+
+    #[test]
+    fn unique_paths_match_sort_and_dedup() {
+        let mut set = U64HashSet::<MurmurHasher>::with_capacity(16);
+        for (data, seed) in inputs().into_iter().zip(SEEDS.into_iter().cycle()) {
+            let mut expected = data.clone();
+            expected.sort_unstable();
+            expected.dedup();
+            let len = data.len();
+
+            // Sorted outputs must match exactly; the others only as sets.
+            assert_eq!(dedup_unique_by_sort_and_dedup(&data), expected, "sort_and_dedup, len {len}");
+            assert_eq!(dedup_unique_by_sort(&data, |d| d.sort_unstable()), expected, "sort, len {len}");
+            let as_set = |mut unique: Vec<u64>| {
+                unique.sort_unstable();
+                unique
+            };
+            assert_eq!(as_set(dedup_unique_by_u64_hash(&data, &mut set)), expected, "u64_hash, len {len}");
+            let hasher = SeededMulSwapMulHasher::with_seed(seed);
+            assert_eq!(as_set(dedup_unique_by_hashed_sort(&data, hasher)), expected, "hashed_sort, len {len}");
+            assert_eq!(as_set(dedup_unique_by_hashed_dlsd_sort(&data, hasher)), expected, "dlsd, len {len}");
+            assert_eq!(as_set(dedup_unique_by_hashed_msd_sort(&data, MulSwapMulHasher)), expected, "msd, len {len}");
+        }
+    }
+
+    #[test]
+    fn dedup_sorted_compacts_in_place() {
+        for data in inputs() {
+            let mut sorted_data = data.clone();
+            sorted_data.sort_unstable();
+            let mut expected = sorted_data.clone();
+            expected.dedup();
+
+            let len = dedup_sorted(&mut sorted_data);
+            assert_eq!(sorted_data[..len], expected, "len {}", data.len());
+        }
+    }
+
+    #[test]
+    fn stable_paths_keep_first_occurrences() {
+        let mut set = U64HashSet::<MurmurHasher>::with_capacity(16);
+        let mut rng = fastrand::Rng::with_seed(48);
+        let mut inputs = inputs();
+        // Many repeats, spread across the input, so most words are kept at an occurrence well before their last.
+        inputs.push((0..100_000).map(|_| rng.u64(..100)).collect());
+        inputs.push((0..100_000).map(|i| [0, u64::MAX, rng.u64(..5000)][i % 3]).collect());
+        for (data, seed) in inputs.into_iter().zip(SEEDS.into_iter().cycle()) {
+            let mut seen = HashSet::new();
+            let expected: Vec<u64> = data.iter().copied().filter(|&d| seen.insert(d)).collect();
+            let len = data.len();
+
+            assert_eq!(dedup_stable_by_u64_hash(&data, &mut set), expected, "u64_hash, len {len}");
+            let hasher = SeededMulSwapMulHasher::with_seed(seed);
+            assert_eq!(dedup_stable_by_hashed_sort(&data, hasher), expected, "hashed_sort, len {len}");
+            // Small words keep their high bits clear under NoopHasher, so distinct words share a group.
+            assert_eq!(dedup_stable_by_hashed_sort(&data, NoopHasher), expected, "hashed_sort noop, len {len}");
+        }
+    }
+
+    #[test]
+    fn stable_hashed_sort_handles_one_huge_group() {
+        // Under NoopHasher, dense ids keep none of their bits above the index, so they all land in one group.
+        let mut rng = fastrand::Rng::with_seed(49);
+        let mut data: Vec<u64> = (0..1 << 20).collect();
+        rng.shuffle(&mut data);
+        // Plus some repeats, so not every word is kept.
+        data.extend((0..1000).map(|_| rng.u64(..1 << 20)));
+        let mut seen = HashSet::new();
+        let expected: Vec<u64> = data.iter().copied().filter(|&d| seen.insert(d)).collect();
+        assert_eq!(dedup_stable_by_hashed_sort(&data, NoopHasher), expected);
+    }
+}
@@ -0,0 +1,131 @@
+//! The open-addressing table behind `U64HashSet` and `U64CountMap`: cache-line buckets of u64 keys, with zero marking
+//! empty slots and no metadata table, so each probe touches a single cache line.
+//!
+//! Zero can't be stored as a key, so the set and the map track it on the side.
+
+use crate::hashers::SeededU64Hasher;
+
+/// A cache line of slots, each holding a key, zero if empty, and whatever the table keeps next to it.
+pub(crate) trait Bucket: Copy {
+    /// Slots per bucket, a power of 2.
+    const SLOTS: usize;
+    /// A bucket of empty slots.
+    const EMPTY: Self;
+
+    fn key(&self, slot: usize) -> u64;
+
+    fn key_mut(&mut self, slot: usize) -> &mut u64;
+
+    /// Copies what `from` keeps next to the key in `from_slot` to `slot`, when the table grows.
+    fn copy_value(&mut self, slot: usize, from: &Self, from_slot: usize);
+}
+
+/// Nonzero u64 keys in buckets of type `B`, hashed with `H`.
+pub(crate) struct DenseTable<B: Bucket, H: SeededU64Hasher> {
+    buckets: Box<[B]>,
+    len: usize,
+    hasher: H,
+}
+
+impl<B: Bucket, H: SeededU64Hasher> DenseTable<B, H> {
+    /// Returns an empty table with at least twice `capacity` slots, so that it holds `capacity` keys without growing.
+    ///
+    /// Panics if that many slots would overflow `usize`.
+    pub(crate) fn with_capacity_and_hasher(capacity: usize, hasher: H) -> Self {
+        let num_slots = capacity.checked_next_power_of_two().and_then(|slots| slots.checked_mul(2));
+        let num_buckets = num_slots.expect("hash table capacity overflow").div_ceil(B::SLOTS);
+        Self {
+            buckets: vec![B::EMPTY; num_buckets].into_boxed_slice(),
+            len: 0,
+            hasher,
+        }
+    }
+
+    /// Empties every bucket, keeping the allocation.
+    pub(crate) fn clear(&mut self) {
+        self.buckets.fill(B::EMPTY);
+        self.len = 0;
+    }
+
+    /// Returns the number of keys in the table.
+    #[inline(always)]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the buckets, in table order.
+    pub(crate) fn buckets(&self) -> &[B] {
+        &self.buckets
+    }
+
+    /// Prefetches the bucket `key` would go in, to find it a little later without waiting on a cache miss.
+    #[inline(always)]
+    pub(crate) fn prefetch(&self, key: u64) {
+        let bucket_i = self.hasher.hash(key) as usize & (self.buckets.len() - 1);
+        // Safety: masking is in bounds because the number of buckets is a power of 2.
+        let bucket = unsafe { self.buckets.get_unchecked(bucket_i) } as *const B as *const i8;
+        // A prefetch for writing, since the bucket is about to be updated. Only on x86-64; elsewhere this does nothing.
+        #[cfg(target_arch = "x86_64")]
+        // Safety: prefetching has no effect on the program's behavior, and needs only SSE, which x86-64 always has.
+        unsafe {
+            std::arch::x86_64::_mm_prefetch::<{ std::arch::x86_64::_MM_HINT_ET0 }>(bucket)
+        };
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = bucket;
+    }
+
+    /// Finds `key`, which must be nonzero, or puts it in the first empty slot of its probe sequence, then calls `f` on
+    /// its bucket and slot. Returns whether `key` was put in an empty slot.
+    ///
+    /// Once more than half its slots are taken, the table doubles, so probe sequences stay short.
+    #[inline(always)]
+    pub(crate) fn find_or_insert(&mut self, key: u64, f: impl FnOnce(&mut B, usize)) -> bool {
+        debug_assert!(key != 0);
+        let hash64 = self.hasher.hash(key);
+        let bucket_mask = self.buckets.len() - 1;
+        // Start at a slot picked by the top bits, which don't pick the bucket.
+        let slot_offset = (hash64 >> (u64::BITS - B::SLOTS.ilog2())) as usize;
+        let mut bucket_i = hash64 as usize;
+
+        loop {
+            // Safety: bucket_mask is correct because the number of buckets is a power of 2.
+            let bucket = unsafe { self.buckets.get_unchecked_mut(bucket_i & bucket_mask) };
+            for slot_i in 0..B::SLOTS {
+                let slot = (slot_i + slot_offset) % B::SLOTS;
+                let element = bucket.key_mut(slot);
+                if *element == 0 {
+                    *element = key;
+                    f(bucket, slot);
+                    self.len += 1;
+                    if self.len > self.buckets.len() * B::SLOTS / 2 {
+                        self.grow();
+                    }
+                    return true;
+                }
+                if *element == key {
+                    f(bucket, slot);
+                    return false;
+                }
+            }
+            bucket_i += 1;
+        }
+    }
+
+    /// Doubles the table and moves every key into it, with what is kept next to it.
+    #[cold]
+    #[inline(never)]
+    fn grow(&mut self) {
+        let num_buckets = self.buckets.len().checked_mul(2).expect("hash table capacity overflow");
+        let old_buckets = std::mem::replace(&mut self.buckets, vec![B::EMPTY; num_buckets].into_boxed_slice());
+        self.len = 0;
+        // Half of the new table's slots is all of the old one's, so none of these inserts grows it again.
+        for from in &old_buckets {
+            for from_slot in 0..B::SLOTS {
+                let key = from.key(from_slot);
+                if key != 0 {
+                    self.find_or_insert(key, |to, slot| to.copy_value(slot, from, from_slot));
+                }
+            }
+        }
+    }
+}
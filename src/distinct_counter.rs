@@ -0,0 +1,444 @@
+//! One interface to every way of counting distinct words, and a registry of them all.
+//!
+//! A `DistinctCounter` is an algorithm paired with the hasher it uses, if any, plus the scratch space (buffers, or a
+//! table to fill) it keeps between calls. The benchmark iterates over `registry` rather than wiring up each algorithm
+//! by hand, and can add counters of its own by implementing the trait, usually with `Counter`.
+
+use crate::counters::*;
+use crate::hashers::{AesHasher, Crc32cHasher, SeededU64Hasher};
+use crate::u64_hash_set::U64HashSet;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use voracious_radix_sort::RadixSort;
+
+#[doc(hidden)]
+pub use foldhash::fast::RandomState as FoldHashBuildHasher;
+
+/// How a counter finds the distinct words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountingMethod {
+    /// Inserts every word into a hash set.
+    HashSet,
+    /// Sorts the words, then counts runs of equal words.
+    Sorting,
+    /// Sorts hashes of the words, then counts runs of equal hashes.
+    HashedSorting,
+}
+
+impl CountingMethod {
+    /// Returns the name used at the start of a counter's name, e.g. "Parallel hashed sorting".
+    pub fn label(self, is_parallel: bool) -> &'static str {
+        match (self, is_parallel) {
+            (CountingMethod::HashSet, false) => "HashSet",
+            (CountingMethod::Sorting, false) => "Sorting",
+            (CountingMethod::HashedSorting, false) => "Hashed sorting",
+            (CountingMethod::HashSet, true) => "Parallel HashSet",
+            (CountingMethod::Sorting, true) => "Parallel sorting",
+            (CountingMethod::HashedSorting, true) => "Parallel hashed sorting",
+        }
+    }
+}
+
+/// A hash function a counter can use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HasherKind {
+    /// The std `HashSet`'s default, through `std::hash::Hash`.
+    SipHash,
+    /// foldhash's fast hasher, through `std::hash::Hash`.
+    FoldHash,
+    NoOp,
+    Murmur,
+    MulSwapMul,
+    SeededMurmur,
+    SeededMulSwapMul,
+    WyHash,
+    Moremur,
+    Rrmxmx,
+    Crc32c,
+    Aes,
+}
+
+impl HasherKind {
+    /// Every hasher, for the algorithms that take any `BuildHasher`.
+    pub const ALL: &'static [HasherKind] = &[
+        HasherKind::SipHash,
+        HasherKind::FoldHash,
+        HasherKind::NoOp,
+        HasherKind::Murmur,
+        HasherKind::MulSwapMul,
+        HasherKind::SeededMurmur,
+        HasherKind::SeededMulSwapMul,
+        HasherKind::WyHash,
+        HasherKind::Moremur,
+        HasherKind::Rrmxmx,
+        HasherKind::Crc32c,
+        HasherKind::Aes,
+    ];
+    /// The `SeededU64Hasher`s, for the algorithms that hash words directly.
+    pub const U64: &'static [HasherKind] = &[
+        HasherKind::NoOp,
+        HasherKind::Murmur,
+        HasherKind::MulSwapMul,
+        HasherKind::SeededMurmur,
+        HasherKind::SeededMulSwapMul,
+        HasherKind::WyHash,
+        HasherKind::Moremur,
+        HasherKind::Rrmxmx,
+        HasherKind::Crc32c,
+        HasherKind::Aes,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HasherKind::SipHash => "SipHash",
+            HasherKind::FoldHash => "FoldHash",
+            HasherKind::NoOp => "NoOp",
+            HasherKind::Murmur => "Murmur",
+            HasherKind::MulSwapMul => "MulSwapMul",
+            HasherKind::SeededMurmur => "seeded Murmur",
+            HasherKind::SeededMulSwapMul => "seeded MulSwapMul",
+            HasherKind::WyHash => "WyHash",
+            HasherKind::Moremur => "Moremur",
+            HasherKind::Rrmxmx => "rrmxmx",
+            HasherKind::Crc32c => "CRC32C",
+            HasherKind::Aes => "AES",
+        }
+    }
+
+    /// Whether the CPU can run this hasher. Only the hardware ones can be missing.
+    pub fn is_supported(self) -> bool {
+        match self {
+            HasherKind::Crc32c => Crc32cHasher::is_supported(),
+            HasherKind::Aes => AesHasher::is_supported(),
+            _ => true,
+        }
+    }
+}
+
+/// Evaluates `$body` with `$h` bound to the `SeededU64Hasher` of `$kind`, which must be one of `HasherKind::U64`.
+/// The seeded hashers are seeded with `$seed` if it is `Some`, and randomly otherwise.
+#[macro_export]
+macro_rules! with_u64_hasher {
+    ($kind:expr, $seed:expr, |$h:ident| $body:expr) => {{
+        use $crate::distinct_counter::HasherKind;
+        use $crate::hashers::*;
+        let seed: Option<u64> = $seed;
+        match $kind {
+            HasherKind::NoOp => {
+                let $h = NoopHasher;
+                $body
+            }
+            HasherKind::Murmur => {
+                let $h = MurmurHasher;
+                $body
+            }
+            HasherKind::MulSwapMul => {
+                let $h = MulSwapMulHasher;
+                $body
+            }
+            HasherKind::SeededMurmur => {
+                let $h = seed.map_or_else(SeededMurmurHasher::random, SeededMurmurHasher::with_seed);
+                $body
+            }
+            HasherKind::SeededMulSwapMul => {
+                let $h = seed.map_or_else(SeededMulSwapMulHasher::random, SeededMulSwapMulHasher::with_seed);
+                $body
+            }
+            HasherKind::WyHash => {
+                let $h = WyHasher;
+                $body
+            }
+            HasherKind::Moremur => {
+                let $h = MoremurHasher;
+                $body
+            }
+            HasherKind::Rrmxmx => {
+                let $h = RrmxmxHasher;
+                $body
+            }
+            HasherKind::Crc32c => {
+                let $h = Crc32cHasher;
+                $body
+            }
+            HasherKind::Aes => {
+                let $h = AesHasher;
+                $body
+            }
+            kind @ (HasherKind::SipHash | HasherKind::FoldHash) => panic!("{kind:?} doesn't hash u64s directly"),
+        }
+    }};
+}
+
+/// Like `with_u64_hasher!`, but binds `$b` to a `BuildHasher`, so it works with every `HasherKind`.
+#[macro_export]
+macro_rules! with_build_hasher {
+    ($kind:expr, $seed:expr, |$b:ident| $body:expr) => {{
+        use $crate::distinct_counter::HasherKind;
+        match $kind {
+            HasherKind::SipHash => {
+                let $b = std::hash::RandomState::new();
+                $body
+            }
+            HasherKind::FoldHash => {
+                let $b = $crate::distinct_counter::FoldHashBuildHasher::default();
+                $body
+            }
+            kind => $crate::with_u64_hasher!(kind, $seed, |h| {
+                let $b = $crate::hashers::BuildU64Hasher(h);
+                // `$body` is shared with the builders above, which aren't Copy, so it may clone `$b`.
+                #[allow(clippy::clone_on_copy)]
+                let result = $body;
+                result
+            }),
+        }
+    }};
+}
+
+/// Counts the distinct words of a slice, with one algorithm and hasher.
+pub trait DistinctCounter {
+    /// Returns the full name, e.g. "HashSet (dense_table + MulSwapMul)": method, algorithm, and hasher if any.
+    fn name(&self) -> String;
+    fn method(&self) -> CountingMethod;
+    fn is_parallel(&self) -> bool;
+    /// Returns the hasher this counter uses, if its method hashes.
+    fn hasher(&self) -> Option<HasherKind>;
+    /// Returns the hashers the algorithm works with. `registry` has one counter for each of them. Empty for the
+    /// algorithms that don't hash.
+    fn supported_hashers(&self) -> &'static [HasherKind];
+    /// Returns about how many bytes of scratch space a call on `len` words allocates, when it starts with none.
+    fn memory_estimate(&self, len: usize) -> usize;
+    /// Returns the number of distinct words in `data`, reusing the scratch space from earlier calls, if any.
+    fn count(&mut self, data: &[u64]) -> usize;
+    /// Frees the scratch space, so the next `count` allocates it again.
+    fn free_scratch(&mut self);
+}
+
+/// A `DistinctCounter` made of a function creating scratch space and a function counting with it.
+pub struct Counter<S, N, C> {
+    method: CountingMethod,
+    algorithm: String,
+    is_parallel: bool,
+    hasher: Option<HasherKind>,
+    supported_hashers: &'static [HasherKind],
+    bytes_per_word: usize,
+    fixed_bytes: usize,
+    new_scratch: N,
+    count: C,
+    scratch: Option<S>,
+}
+
+impl<S, N, C> Counter<S, N, C>
+where
+    N: FnMut() -> S,
+    C: FnMut(&[u64], &mut S) -> usize,
+{
+    /// Returns a serial counter that doesn't hash and allocates nothing, named after `method` and `algorithm`. The
+    /// other methods fill in the rest.
+    pub fn new(method: CountingMethod, algorithm: impl Into<String>, new_scratch: N, count: C) -> Self {
+        Self {
+            method,
+            algorithm: algorithm.into(),
+            is_parallel: false,
+            hasher: None,
+            supported_hashers: &[],
+            bytes_per_word: 0,
+            fixed_bytes: 0,
+            new_scratch,
+            count,
+            scratch: None,
+        }
+    }
+
+    pub fn parallel(mut self) -> Self {
+        self.is_parallel = true;
+        self
+    }
+
+    /// Sets the hasher, one of the algorithm's `supported_hashers`.
+    pub fn hashed_with(mut self, hasher: HasherKind, supported_hashers: &'static [HasherKind]) -> Self {
+        debug_assert!(supported_hashers.contains(&hasher));
+        self.hasher = Some(hasher);
+        self.supported_hashers = supported_hashers;
+        self
+    }
+
+    /// Sets the memory estimate to `bytes_per_word` bytes per input word, plus `fixed_bytes`.
+    pub fn memory(mut self, bytes_per_word: usize, fixed_bytes: usize) -> Self {
+        self.bytes_per_word = bytes_per_word;
+        self.fixed_bytes = fixed_bytes;
+        self
+    }
+}
+
+impl<S, N, C> DistinctCounter for Counter<S, N, C>
+where
+    N: FnMut() -> S,
+    C: FnMut(&[u64], &mut S) -> usize,
+{
+    fn name(&self) -> String {
+        let label = self.method.label(self.is_parallel);
+        match self.hasher {
+            Some(hasher) => format!("{label} ({} + {})", self.algorithm, hasher.name()),
+            None => format!("{label} ({})", self.algorithm),
+        }
+    }
+
+    fn method(&self) -> CountingMethod {
+        self.method
+    }
+
+    fn is_parallel(&self) -> bool {
+        self.is_parallel
+    }
+
+    fn hasher(&self) -> Option<HasherKind> {
+        self.hasher
+    }
+
+    fn supported_hashers(&self) -> &'static [HasherKind] {
+        self.supported_hashers
+    }
+
+    fn memory_estimate(&self, len: usize) -> usize {
+        self.bytes_per_word * len + self.fixed_bytes
+    }
+
+    fn count(&mut self, data: &[u64]) -> usize {
+        let scratch = self.scratch.get_or_insert_with(&mut self.new_scratch);
+        (self.count)(data, scratch)
+    }
+
+    fn free_scratch(&mut self) {
+        self.scratch = None;
+    }
+}
+
+/// Returns about how many bytes a std `HashSet<u64>` (a SwissTable) with room for `capacity` words takes: 8 bytes
+/// per slot plus a byte of metadata, with slots at most 7/8 full.
+pub fn swiss_table_bytes(capacity: usize) -> usize {
+    9 * (capacity * 8 / 7).next_power_of_two()
+}
+
+/// Returns about how many bytes a `U64HashSet` with room for `capacity` words takes.
+pub fn dense_table_bytes(capacity: usize) -> usize {
+    8 * 2 * capacity.next_power_of_two()
+}
+
+type SortCount = fn(&[u64], &mut Scratch) -> usize;
+type ParallelSortCount = fn(&[u64], &mut Vec<u64>, usize) -> usize;
+type HashedSortCount<H> = fn(&[u64], H, &mut Scratch) -> usize;
+
+/// Returns a counter for every algorithm in this crate and every hasher it supports, skipping hashers the CPU can't
+/// run.
+///
+/// `capacity` is an initial sizing hint: the hash sets start with room for that many distinct words, and grow if they
+/// count more, at the cost of rehashing. The seeded hashers are seeded with `seed` if it is `Some`, and randomly
+/// otherwise. `num_threads` is the number of threads for the parallel radix sorts.
+pub fn registry(capacity: usize, seed: Option<u64>, num_threads: usize) -> Vec<Box<dyn DistinctCounter>> {
+    let mut counters: Vec<Box<dyn DistinctCounter>> = Vec::new();
+    let hashers = |kinds: &'static [HasherKind]| kinds.iter().copied().filter(|kind| kind.is_supported());
+
+    for kind in hashers(HasherKind::ALL) {
+        with_build_hasher!(kind, seed, |build_hasher| {
+            let counter = Counter::new(
+                CountingMethod::HashSet,
+                "SwissTable",
+                move || HashSet::with_capacity_and_hasher(capacity, build_hasher.clone()),
+                count_unique_by_hash,
+            );
+            counters.push(Box::new(counter.hashed_with(kind, HasherKind::ALL).memory(0, swiss_table_bytes(capacity))));
+        });
+    }
+    for kind in hashers(HasherKind::U64) {
+        with_u64_hasher!(kind, seed, |h| push_u64_hasher_counters(&mut counters, kind, h, capacity, num_threads));
+    }
+
+    let sorts: [(&str, usize, SortCount); 9] = [
+        ("merge sort", 12, |data, scratch| count_unique_by_sort(data, scratch, |v| v.sort())),
+        ("quick sort", 8, |data, scratch| count_unique_by_sort(data, scratch, |v| v.sort_unstable())),
+        ("radix sort", 16, |data, scratch| count_unique_by_sort(data, scratch, |v| v.voracious_sort())),
+        ("wide merge sort", 16, count_unique_by_wide_merge_sort::<256>),
+        ("fused wide_merge_sort_and_count", 16, count_unique_by_fused_wide_merge_sort),
+        // Fan-in sweep. Larger fan-ins need fewer passes, but keep more runs in flight: about one cache line per run
+        // plus the tournament tree, which eventually spills out of L1/L2. Fan-in 256 is "wide merge sort" above.
+        ("wide merge sort, fan-in 16", 16, count_unique_by_wide_merge_sort::<16>),
+        ("wide merge sort, fan-in 64", 16, count_unique_by_wide_merge_sort::<64>),
+        ("wide merge sort, fan-in 1024", 16, count_unique_by_wide_merge_sort::<1024>),
+        ("wide merge sort, fan-in 4096", 16, count_unique_by_wide_merge_sort::<4096>),
+    ];
+    for (algorithm, bytes_per_word, count) in sorts {
+        let counter = Counter::new(CountingMethod::Sorting, algorithm, Scratch::default, count);
+        counters.push(Box::new(counter.memory(bytes_per_word, 0)));
+    }
+    // Spills 16 runs to temporary files, so this mostly measures the disk.
+    let counter = Counter::new(
+        CountingMethod::Sorting,
+        "external wide merge sort, 1/8 of input in memory",
+        || (),
+        |data, _| count_unique_by_external_sort(data, data.len()).expect("external sort failed"),
+    );
+    counters.push(Box::new(counter.memory(1, 0)));
+
+    let parallel_sorts: [(&str, usize, ParallelSortCount); 3] = [
+        ("merge sort", 12, |data, sorted_data, _| count_unique_by_parallel_sort(data, sorted_data, |v| v.par_sort())),
+        ("quick sort", 8, |data, sorted_data, _| {
+            count_unique_by_parallel_sort(data, sorted_data, |v| v.par_sort_unstable())
+        }),
+        ("radix sort", 16, |data, sorted_data, num_threads| {
+            count_unique_by_parallel_sort(data, sorted_data, |v| v.voracious_mt_sort(num_threads))
+        }),
+    ];
+    for (algorithm, bytes_per_word, count) in parallel_sorts {
+        let counter = Counter::new(CountingMethod::Sorting, algorithm, Vec::new, move |data, sorted_data| {
+            count(data, sorted_data, num_threads)
+        });
+        counters.push(Box::new(counter.parallel().memory(bytes_per_word, 0)));
+    }
+    let counter = Counter::new(
+        CountingMethod::Sorting,
+        "wide merge sort",
+        Scratch::default,
+        count_unique_by_parallel_wide_merge_sort,
+    );
+    counters.push(Box::new(counter.parallel().memory(16, 0)));
+    counters
+}
+
+/// Adds the counters that hash words directly to `counters`, with hasher `h`.
+fn push_u64_hasher_counters<H: SeededU64Hasher + Send + Sync + 'static>(
+    counters: &mut Vec<Box<dyn DistinctCounter>>,
+    kind: HasherKind,
+    h: H,
+    capacity: usize,
+    num_threads: usize,
+) {
+    let counter = Counter::new(
+        CountingMethod::HashSet,
+        "dense_table",
+        move || U64HashSet::with_capacity_and_hasher(capacity, h),
+        count_unique_by_u64_hash,
+    );
+    counters.push(Box::new(counter.hashed_with(kind, HasherKind::U64).memory(0, dense_table_bytes(capacity))));
+
+    let hashed_sorts: [(&str, usize, HashedSortCount<H>); 6] = [
+        ("radix", 16, count_unique_by_hashed_sort),
+        ("dlsd", 16, count_unique_by_hashed_dlsd_sort),
+        ("fused dlsd_and_count", 16, count_unique_by_fused_hashed_dlsd_sort),
+        // Only one of its buffers is full-size.
+        ("fused lean dlsd_and_count", 8, count_unique_by_fused_lean_hashed_dlsd_sort),
+        ("msd", 8, count_unique_by_hashed_msd_sort),
+        ("fused msd_and_count", 8, count_unique_by_fused_hashed_msd_sort),
+    ];
+    for (algorithm, bytes_per_word, count) in hashed_sorts {
+        let counter =
+            Counter::new(CountingMethod::HashedSorting, algorithm, Scratch::default, move |data, scratch| {
+                count(data, h, scratch)
+            });
+        counters.push(Box::new(counter.hashed_with(kind, HasherKind::U64).memory(bytes_per_word, 0)));
+    }
+
+    let counter = Counter::new(CountingMethod::HashedSorting, "radix", Vec::new, move |data, sorted_data| {
+        count_unique_by_hashed_parallel_sort(data, h, sorted_data, |v| v.voracious_mt_sort(num_threads))
+    });
+    counters.push(Box::new(counter.parallel().hashed_with(kind, HasherKind::U64).memory(16, 0)));
+}
@@ -1,120 +1,195 @@
 
 
-use crate::hashers::StatelessU64Hasher;
+use crate::hashers::SeededU64Hasher;
+use crate::phase_timing;
+use crate::uninit::{HASH_BLOCK, as_uninit_mut, hash_batch_into, uninit_words};
+use std::mem::MaybeUninit;
 
 const LG_RADIX: u32 = 10;
 const RADIX: usize = 1 << LG_RADIX;
 const WORD_BITS: u32 = 64;
 const MAX_PASSES: usize = WORD_BITS.div_ceil(LG_RADIX) as usize;
 const CHUNK_SIZE: usize = 4;
+/// Budget of insertion-sort shifts per word in a final-pass group, before we give up on insertion sort for that group.
+const INSERTION_SHIFTS_PER_WORD: usize = 8;
 
-pub fn dlsd_sort<Hasher: StatelessU64Hasher>(orig_data: &[u64]) -> Vec<u64> {
+/// Returns the hashes of `orig_data` under `hasher`, sorted, using a radix sort on 10-bit digits from least to most
+/// significant.
+pub fn dlsd_sort<Hasher: SeededU64Hasher>(orig_data: &[u64], hasher: Hasher) -> Vec<u64> {
+    let len = orig_data.len();
+    let mut data = Vec::with_capacity(len);
+    dlsd_sort_with_scratch(orig_data, hasher, &mut data.spare_capacity_mut()[..len], &mut uninit_words(len));
+    // Safety: `dlsd_sort_with_scratch` wrote all `len` words.
+    unsafe { data.set_len(len) };
+    data
+}
+
+/// Like `dlsd_sort`, but writes the sorted hashes to `data` and uses `aux` as scratch space, without allocating.
+/// Returns `data`, now initialized.
+///
+/// Both buffers must be the same length as `orig_data`. They may be uninitialized, and their contents on entry are
+/// ignored, so they can be reused across calls.
+pub fn dlsd_sort_with_scratch<'a, Hasher: SeededU64Hasher>(
+    orig_data: &[u64],
+    hasher: Hasher,
+    data: &'a mut [MaybeUninit<u64>],
+    aux: &mut [MaybeUninit<u64>],
+) -> &'a mut [u64] {
+    assert!(data.len() == orig_data.len() && aux.len() == orig_data.len());
+    if orig_data.len() <= 1 {
+        return hash_batch_into(hasher, orig_data, data);
+    }
     let passes = orig_data
         .len()
         .next_power_of_two()
         .ilog2()
         .div_ceil(LG_RADIX) as usize;
-    assert!(orig_data.len() % CHUNK_SIZE == 0);
     // First gather counts.
-    let (mut data, counts) = match passes {
-        0 => compute_counts::<0, Hasher>(orig_data),
-        1 => compute_counts::<1, Hasher>(orig_data),
-        2 => compute_counts::<2, Hasher>(orig_data),
-        3 => compute_counts::<3, Hasher>(orig_data),
-        4 => compute_counts::<4, Hasher>(orig_data),
-        5 => compute_counts::<5, Hasher>(orig_data),
-        6 => compute_counts::<6, Hasher>(orig_data),
-        7 => compute_counts::<7, Hasher>(orig_data),
-        8 => compute_counts::<8, Hasher>(orig_data),
-        9 => compute_counts::<9, Hasher>(orig_data),
-        10 => compute_counts::<10, Hasher>(orig_data),
-        11 => compute_counts::<11, Hasher>(orig_data),
-        12 => compute_counts::<12, Hasher>(orig_data),
-        13 => compute_counts::<13, Hasher>(orig_data),
+    let hash_phase = phase_timing::phase("dlsd: hash + histogram");
+    let counts = match passes {
+        0 => compute_counts::<0, Hasher>(orig_data, hasher, data),
+        1 => compute_counts::<1, Hasher>(orig_data, hasher, data),
+        2 => compute_counts::<2, Hasher>(orig_data, hasher, data),
+        3 => compute_counts::<3, Hasher>(orig_data, hasher, data),
+        4 => compute_counts::<4, Hasher>(orig_data, hasher, data),
+        5 => compute_counts::<5, Hasher>(orig_data, hasher, data),
+        6 => compute_counts::<6, Hasher>(orig_data, hasher, data),
+        7 => compute_counts::<7, Hasher>(orig_data, hasher, data),
+        8 => compute_counts::<8, Hasher>(orig_data, hasher, data),
+        9 => compute_counts::<9, Hasher>(orig_data, hasher, data),
+        10 => compute_counts::<10, Hasher>(orig_data, hasher, data),
+        11 => compute_counts::<11, Hasher>(orig_data, hasher, data),
+        12 => compute_counts::<12, Hasher>(orig_data, hasher, data),
+        13 => compute_counts::<13, Hasher>(orig_data, hasher, data),
         _ => unreachable!("Too many passes!"),
     };
-    let mut aux = vec![0u64; data.len()];
-    let mut from = &mut data[..];
-    let mut to = &mut aux[..];
+    drop(hash_phase);
+    // Safety: `compute_counts` hashed every word into `data`.
+    let mut from = unsafe { data.assume_init_mut() };
+    let mut to = &mut *aux;
     // Now do passes. Non-last passes just do dealing.
-    for pass in 0..passes - 1 {
+    for (pass, pass_counts) in counts.iter().enumerate().take(passes - 1) {
+        let _phase = phase_timing::indexed_phase("dlsd: scatter pass", pass);
         let mut heads = [0usize; RADIX];
         let mut pos = 0;
         for i in 0..RADIX {
             heads[i] = pos;
-            pos += counts[pass][i];
+            pos += pass_counts[i];
         }
 
-        for chunk in from.as_chunks::<CHUNK_SIZE>().0 {
-            for word in chunk {
-                let radix = read_radix(*word, pass, passes);
-                unsafe {
-                    let pos = heads.get_unchecked_mut(radix);
-                    *to.get_unchecked_mut(*pos) = *word;
-                    *pos += 1;
-                }
+        let mut deal = |word: u64| {
+            let radix = read_radix(word, pass, passes);
+            unsafe {
+                let pos = heads.get_unchecked_mut(radix);
+                to.get_unchecked_mut(*pos).write(word);
+                *pos += 1;
+            }
+        };
+        let (chunks, remainder) = from.as_chunks::<CHUNK_SIZE>();
+        for chunk in chunks {
+            for &word in chunk {
+                deal(word);
             }
         }
-        std::mem::swap(&mut from, &mut to);
+        for &word in remainder {
+            deal(word);
+        }
+        // Safety: the counts add up to the length, so the pass wrote every word of `to`. The next pass writes `from`
+        // only through `MaybeUninit::write`, with words it read.
+        (from, to) = (unsafe { to.assume_init_mut() }, unsafe { as_uninit_mut(from) });
     }
 
+    let _phase = phase_timing::phase("dlsd: final insertion pass");
     // Last pass does dealing and fused insertion sort.
     let pass = passes - 1;
     #[derive(Clone, Copy)]
     struct Head {
         start: usize,
         pos: usize,
+        shifts_left: usize,
+        fallback: bool,
     }
-    let mut heads = [Head { start: 0, pos: 0 }; RADIX];
+    let mut heads = [Head { start: 0, pos: 0, shifts_left: 0, fallback: false }; RADIX];
     let mut pos = 0;
     for i in 0..RADIX {
         heads[i] = Head {
             start: pos,
-            pos: pos,
+            pos,
+            shifts_left: counts[pass][i] * INSERTION_SHIFTS_PER_WORD,
+            fallback: false,
         };
         pos += counts[pass][i];
     }
-    for chunk in from.as_chunks::<CHUNK_SIZE>().0 {
-        for &word in chunk {
-            let radix = read_radix(word, pass, passes);
-            let head = unsafe { heads.get_unchecked_mut(radix) };
-            // Insertion sort backwards towards the beginning of the group.
-            let mut j = head.pos;
-            while j > head.start && unsafe { *to.get_unchecked(j - 1) } > word {
-                unsafe { *to.get_unchecked_mut(j) = *to.get_unchecked(j - 1) };
-                j -= 1;
-            }
-            unsafe { *to.get_unchecked_mut(j) = word };
+    let mut insert = |word: u64| {
+        let radix = read_radix(word, pass, passes);
+        let head = unsafe { heads.get_unchecked_mut(radix) };
+        if head.fallback {
+            unsafe { to.get_unchecked_mut(head.pos).write(word) };
             head.pos += 1;
+            return;
+        }
+        // Insertion sort backwards towards the beginning of the group, which has been written up to `head.pos`.
+        let mut j = head.pos;
+        while j > head.start && unsafe { to.get_unchecked(j - 1).assume_init() } > word {
+            unsafe { *to.get_unchecked_mut(j) = *to.get_unchecked(j - 1) };
+            j -= 1;
+        }
+        unsafe { to.get_unchecked_mut(j).write(word) };
+        let shifts = head.pos - j;
+        head.pos += 1;
+        // Many distinct words sharing the sorted bits (e.g. unhashed data with little entropy in the top bits)
+        // make insertion sort quadratic. Once a group blows its budget, just append to it and sort it at the end.
+        if shifts > head.shifts_left {
+            head.fallback = true;
+        } else {
+            head.shifts_left -= shifts;
+        }
+    };
+    let (chunks, remainder) = from.as_chunks::<CHUNK_SIZE>();
+    for chunk in chunks {
+        for &word in chunk {
+            insert(word);
+        }
+    }
+    for &word in remainder {
+        insert(word);
+    }
+    // Safety: as in the scatter passes, every word of `to` has been written.
+    let to = unsafe { to.assume_init_mut() };
+    for head in &heads {
+        if head.fallback {
+            to[head.start..head.pos].sort_unstable();
         }
     }
     if passes % 2 == 1 {
-        to.copy_from_slice(from);
+        from.copy_from_slice(to);
     }
-    data
+    // Safety: either the last pass wrote to `data`, or its result was just copied there.
+    unsafe { data.assume_init_mut() }
 }
 
-fn compute_counts<const PASSES: usize, Hasher: StatelessU64Hasher>(
+fn compute_counts<const PASSES: usize, Hasher: SeededU64Hasher>(
     orig_data: &[u64],
-) -> (Vec<u64>, [[usize; RADIX]; MAX_PASSES]) {
+    hasher: Hasher,
+    data: &mut [MaybeUninit<u64>],
+) -> [[usize; RADIX]; MAX_PASSES] {
     let mut counts = [[0; RADIX]; MAX_PASSES];
-    let mut data = Vec::with_capacity(orig_data.len());
-    data.extend(orig_data
-        .as_chunks::<CHUNK_SIZE>().0
-        .iter()
-        .flat_map(|chunk| {
-            chunk.map(|word| {
-                let h = Hasher::hash(word);
-                for pass in 0..PASSES {
-                    let radix = read_radix(h, pass, PASSES);
-                    unsafe {
-                        *counts.get_unchecked_mut(pass).get_unchecked_mut(radix) += 1;
-                    }
+    let mut hashes = [0u64; HASH_BLOCK];
+    // Hash a block at a time into `hashes`, then histogram it and copy it out while it's still in L1.
+    for (block, data_block) in orig_data.chunks(HASH_BLOCK).zip(data.chunks_mut(HASH_BLOCK)) {
+        let hashes = &mut hashes[..block.len()];
+        hasher.hash_batch(block, hashes);
+        data_block.write_copy_of_slice(hashes);
+        for &h in hashes.iter() {
+            for pass in 0..PASSES {
+                let radix = read_radix(h, pass, PASSES);
+                unsafe {
+                    *counts.get_unchecked_mut(pass).get_unchecked_mut(radix) += 1;
                 }
-                h
-            })
-        }));
-    (data, counts)
+            }
+        }
+    }
+    counts
 }
 
 #[inline(always)]
@@ -123,3 +198,24 @@ fn read_radix(word: u64, pass: usize, passes: usize) -> usize {
     let shift = WORD_BITS - ((passes - pass) as u32 * LG_RADIX);
     ((word >> shift) & MASK) as usize
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashers::NoopHasher;
+
+    #[test]
+    fn skewed_groups_fall_back() {
+        // Keys masked like the benchmark's `MaskStyle`s, unhashed. With 100k words only the top 17 bits are sorted
+        // before the last pass, so the low-bits and spread-out keys all land in one group of distinct words, which
+        // blows its insertion budget and falls back to `sort_unstable`. The high-bits keys don't.
+        let masks = [(1 << 40) - 1, ((1 << 40) - 1) & 0x5555_5555_5555_5555, (1u64 << 20).wrapping_neg()];
+        let mut rng = fastrand::Rng::with_seed(1);
+        for mask in masks {
+            let data: Vec<u64> = (0..100_000).map(|_| rng.u64(..) & mask).collect();
+            let mut expected = data.clone();
+            expected.sort_unstable();
+            assert_eq!(dlsd_sort(&data, NoopHasher), expected, "mask {mask:#x}");
+        }
+    }
+}
@@ -1,141 +1,533 @@
-use crate::hashers::StatelessU64Hasher;
+use crate::counters::count_unique_in_sorted;
+use crate::hashers::SeededU64Hasher;
+use crate::msd::partition;
+use crate::phase_timing;
+use crate::uninit::{HASH_BLOCK, as_uninit_mut, grow, uninit_words};
+use std::mem::MaybeUninit;
 
 const LG_RADIX: u32 = 10;
 const RADIX: usize = 1 << LG_RADIX;
 const WORD_BITS: u32 = 64;
 const MAX_PASSES: usize = WORD_BITS.div_ceil(LG_RADIX) as usize;
 const CHUNK_SIZE: usize = 4;
+/// Budget of insertion-sort shifts per word in a final-pass group, before we give up on insertion sort for that group.
+const INSERTION_SHIFTS_PER_WORD: usize = 8;
 
-pub fn dlsd_sort_and_count<Hasher: StatelessU64Hasher>(orig_data: &[u64]) -> usize {
+/// Returns the number of distinct hashes of `orig_data` under `hasher`: like `dlsd_sort` followed by counting, but
+/// counts during the last pass, so the sorted array is never scanned a second time.
+pub fn dlsd_sort_and_count<Hasher: SeededU64Hasher>(orig_data: &[u64], hasher: Hasher) -> usize {
+    let len = orig_data.len();
+    dlsd_sort_and_count_with_scratch(orig_data, hasher, &mut uninit_words(len), &mut uninit_words(len))
+}
+
+/// Like `dlsd_sort_and_count`, but uses the caller's `data` and `aux` as scratch space, without allocating.
+///
+/// Both buffers must be the same length as `orig_data`. They may be uninitialized, and their contents on entry are
+/// ignored, so they can be reused across calls.
+pub fn dlsd_sort_and_count_with_scratch<Hasher: SeededU64Hasher>(
+    orig_data: &[u64],
+    hasher: Hasher,
+    data: &mut [MaybeUninit<u64>],
+    aux: &mut [MaybeUninit<u64>],
+) -> usize {
+    assert!(data.len() == orig_data.len() && aux.len() == orig_data.len());
+    if orig_data.len() <= 1 {
+        return orig_data.len();
+    }
+    let LastPass { from, to, counts, sum_of_radixes, last_pass_radix } =
+        hash_and_scatter(orig_data, hasher, data, aux);
+
+    let _phase = phase_timing::phase("dlsd_and_count: fused insertion/count pass");
+    deal_and_count(from, to, &counts, sum_of_radixes, last_pass_radix)
+}
+
+/// The last pass of `dlsd_sort_and_count` and `sort_and_count_bucket`: deals `from` into `to` by the last pass's digit,
+/// whose histogram is `counts`, insertion sorting each group and counting its unique words as they arrive. Returns the
+/// count.
+///
+/// Only ever reads back the part of each group of `to` that it has written, so `to` may be uninitialized.
+#[inline(always)]
+fn deal_and_count(
+    from: &[u64],
+    to: &mut [MaybeUninit<u64>],
+    counts: &[usize; RADIX],
+    sum_of_radixes: u32,
+    last_pass_radix: u32,
+) -> usize {
+    let mut heads = last_pass_heads(counts);
+    let sorted_bits_mask = (1u64 << (WORD_BITS - sum_of_radixes)).wrapping_neg();
+    let mut unique_count = 0;
+    let mut insert = |word: u64| {
+        let radix = read_last_pass_radix(word, last_pass_radix);
+        let head = unsafe { heads.get_unchecked_mut(radix) };
+        if head.fallback {
+            unsafe { to.get_unchecked_mut(head.pos).write(word) };
+            head.pos += 1;
+            return;
+        }
+        // Words arrive in each group sorted by their sorted bits. Once a word's sorted bits differ from those of
+        // the largest word so far, nothing earlier in the group can equal any later word.
+        //
+        // Stay in cache: once we've finished with a group, reset back to the beginning of the group.
+        //
+        // This is because we don't actually care about sorted order: we just care about the count.
+        if head.pos > head.start {
+            let last_word = unsafe { to.get_unchecked(head.pos - 1).assume_init() };
+            if (last_word & sorted_bits_mask) != (word & sorted_bits_mask) {
+                head.pos = head.start;
+            }
+        }
+        // Insertion sort backwards towards the beginning of the group.
+        let mut j = head.pos;
+        while j > head.start && unsafe { to.get_unchecked(j - 1).assume_init() } > word {
+            unsafe { *to.get_unchecked_mut(j) = *to.get_unchecked(j - 1) };
+            j -= 1;
+        }
+        unsafe { to.get_unchecked_mut(j).write(word) };
+        unique_count += (j == head.start || unsafe { to.get_unchecked(j - 1).assume_init() } < word) as usize;
+        let shifts = head.pos - j;
+        head.pos += 1;
+        // Many distinct words sharing the sorted bits (e.g. unhashed data with little entropy in the top bits)
+        // make insertion sort quadratic. Once a group blows its budget, stop counting it incrementally: just
+        // append to it, and sort and count it at the end.
+        if shifts > head.shifts_left {
+            unique_count -= count_unique_in_sorted(unsafe { to[head.start..head.pos].assume_init_ref() });
+            head.fallback = true;
+        } else {
+            head.shifts_left -= shifts;
+        }
+    };
+    let (chunks, remainder) = from.as_chunks::<CHUNK_SIZE>();
+    for chunk in chunks {
+        for &word in chunk {
+            insert(word);
+        }
+    }
+    for &word in remainder {
+        insert(word);
+    }
+    for head in &heads {
+        if head.fallback {
+            let group = unsafe { to[head.start..head.pos].assume_init_mut() };
+            group.sort_unstable();
+            unique_count += count_unique_in_sorted(group);
+        }
+    }
+    unique_count
+}
+
+/// Returns each distinct hash of `orig_data` under `hasher` with its number of occurrences, in no particular order:
+/// like `dlsd_sort` followed by run-length counting, but counts each run during the last pass, while it is in cache.
+pub fn dlsd_sort_and_count_occurrences<Hasher: SeededU64Hasher>(
+    orig_data: &[u64],
+    hasher: Hasher,
+) -> Vec<(u64, usize)> {
+    let len = orig_data.len();
+    let mut occurrences = Vec::new();
+    dlsd_sort_and_count_occurrences_with_scratch(
+        orig_data,
+        hasher,
+        &mut uninit_words(len),
+        &mut uninit_words(len),
+        &mut occurrences,
+    );
+    occurrences
+}
+
+/// Like `dlsd_sort_and_count_occurrences`, but uses the caller's `data` and `aux` as scratch space, and replaces the
+/// contents of `occurrences` with the result.
+///
+/// Both buffers must be the same length as `orig_data`. They may be uninitialized, and their contents on entry are
+/// ignored, so they can be reused across calls.
+pub fn dlsd_sort_and_count_occurrences_with_scratch<Hasher: SeededU64Hasher>(
+    orig_data: &[u64],
+    hasher: Hasher,
+    data: &mut [MaybeUninit<u64>],
+    aux: &mut [MaybeUninit<u64>],
+    occurrences: &mut Vec<(u64, usize)>,
+) {
+    assert!(data.len() == orig_data.len() && aux.len() == orig_data.len());
+    occurrences.clear();
+    if orig_data.len() <= 1 {
+        occurrences.extend(orig_data.iter().map(|&word| (hasher.hash(word), 1)));
+        return;
+    }
+    let LastPass { from, to, counts, sum_of_radixes, last_pass_radix } =
+        hash_and_scatter(orig_data, hasher, data, aux);
+
+    let _phase = phase_timing::phase("dlsd_and_count: fused insertion/run-length pass");
+    // As in `dlsd_sort_and_count`, but a run can only be counted once all of it has arrived, which is when its group
+    // moves on to the next value of the sorted bits.
+    let mut heads = last_pass_heads(&counts);
+    let sorted_bits_mask = (1u64 << (WORD_BITS - sum_of_radixes)).wrapping_neg();
+    let mut insert = |word: u64| {
+        let radix = read_last_pass_radix(word, last_pass_radix);
+        let head = unsafe { heads.get_unchecked_mut(radix) };
+        if head.fallback {
+            unsafe { to.get_unchecked_mut(head.pos).write(word) };
+            head.pos += 1;
+            return;
+        }
+        if head.pos > head.start {
+            let last_word = unsafe { to.get_unchecked(head.pos - 1).assume_init() };
+            if (last_word & sorted_bits_mask) != (word & sorted_bits_mask) {
+                push_runs(unsafe { to[head.start..head.pos].assume_init_ref() }, occurrences);
+                head.pos = head.start;
+            }
+        }
+        let mut j = head.pos;
+        while j > head.start && unsafe { to.get_unchecked(j - 1).assume_init() } > word {
+            unsafe { *to.get_unchecked_mut(j) = *to.get_unchecked(j - 1) };
+            j -= 1;
+        }
+        unsafe { to.get_unchecked_mut(j).write(word) };
+        let shifts = head.pos - j;
+        head.pos += 1;
+        // Runs already pushed were complete; the rest of the group is sorted and counted at the end.
+        if shifts > head.shifts_left {
+            head.fallback = true;
+        } else {
+            head.shifts_left -= shifts;
+        }
+    };
+    let (chunks, remainder) = from.as_chunks::<CHUNK_SIZE>();
+    for chunk in chunks {
+        for &word in chunk {
+            insert(word);
+        }
+    }
+    for &word in remainder {
+        insert(word);
+    }
+    for head in &heads {
+        let group = unsafe { to[head.start..head.pos].assume_init_mut() };
+        if head.fallback {
+            group.sort_unstable();
+        }
+        push_runs(group, occurrences);
+    }
+}
+
+/// Pushes each run of equal words in `sorted_data`, with its length, to `occurrences`.
+fn push_runs(sorted_data: &[u64], occurrences: &mut Vec<(u64, usize)>) {
+    occurrences.extend(sorted_data.chunk_by(|a, b| a == b).map(|run| (run[0], run.len())));
+}
+
+/// The hashes just before the last pass: sorted by the bits below the last pass's digit in `from`, to be dealt into
+/// `to` by that digit, whose histogram is `counts`.
+struct LastPass<'a> {
+    from: &'a [u64],
+    to: &'a mut [MaybeUninit<u64>],
+    counts: [usize; RADIX],
+    sum_of_radixes: u32,
+    last_pass_radix: u32,
+}
+
+/// Hashes `orig_data` into `data` and does every pass but the last, which `dlsd_sort_and_count` and
+/// `dlsd_sort_and_count_occurrences` each fuse with their own counting. Needs at least 2 words.
+fn hash_and_scatter<'a, Hasher: SeededU64Hasher>(
+    orig_data: &[u64],
+    hasher: Hasher,
+    data: &'a mut [MaybeUninit<u64>],
+    aux: &'a mut [MaybeUninit<u64>],
+) -> LastPass<'a> {
     let sum_of_radixes = orig_data.len().next_power_of_two().ilog2();
     let passes = sum_of_radixes.div_ceil(LG_RADIX) as usize;
     let last_pass_radix = sum_of_radixes - (passes as u32 - 1) * LG_RADIX;
-    assert!(orig_data.len() % CHUNK_SIZE == 0);
     // First gather counts.
-    let (mut data, counts) = match passes {
-        0 => compute_counts::<0, Hasher>(orig_data, last_pass_radix),
-        1 => compute_counts::<1, Hasher>(orig_data, last_pass_radix),
-        2 => compute_counts::<2, Hasher>(orig_data, last_pass_radix),
-        3 => compute_counts::<3, Hasher>(orig_data, last_pass_radix),
-        4 => compute_counts::<4, Hasher>(orig_data, last_pass_radix),
-        5 => compute_counts::<5, Hasher>(orig_data, last_pass_radix),
-        6 => compute_counts::<6, Hasher>(orig_data, last_pass_radix),
-        7 => compute_counts::<7, Hasher>(orig_data, last_pass_radix),
-        8 => compute_counts::<8, Hasher>(orig_data, last_pass_radix),
-        9 => compute_counts::<9, Hasher>(orig_data, last_pass_radix),
-        10 => compute_counts::<10, Hasher>(orig_data, last_pass_radix),
-        11 => compute_counts::<11, Hasher>(orig_data, last_pass_radix),
-        12 => compute_counts::<12, Hasher>(orig_data, last_pass_radix),
-        13 => compute_counts::<13, Hasher>(orig_data, last_pass_radix),
+    let hash_phase = phase_timing::phase("dlsd_and_count: hash + histogram");
+    let counts = match passes {
+        0 => compute_counts::<0, Hasher>(orig_data, hasher, data, last_pass_radix),
+        1 => compute_counts::<1, Hasher>(orig_data, hasher, data, last_pass_radix),
+        2 => compute_counts::<2, Hasher>(orig_data, hasher, data, last_pass_radix),
+        3 => compute_counts::<3, Hasher>(orig_data, hasher, data, last_pass_radix),
+        4 => compute_counts::<4, Hasher>(orig_data, hasher, data, last_pass_radix),
+        5 => compute_counts::<5, Hasher>(orig_data, hasher, data, last_pass_radix),
+        6 => compute_counts::<6, Hasher>(orig_data, hasher, data, last_pass_radix),
+        7 => compute_counts::<7, Hasher>(orig_data, hasher, data, last_pass_radix),
+        8 => compute_counts::<8, Hasher>(orig_data, hasher, data, last_pass_radix),
+        9 => compute_counts::<9, Hasher>(orig_data, hasher, data, last_pass_radix),
+        10 => compute_counts::<10, Hasher>(orig_data, hasher, data, last_pass_radix),
+        11 => compute_counts::<11, Hasher>(orig_data, hasher, data, last_pass_radix),
+        12 => compute_counts::<12, Hasher>(orig_data, hasher, data, last_pass_radix),
+        13 => compute_counts::<13, Hasher>(orig_data, hasher, data, last_pass_radix),
         _ => unreachable!("Too many passes!"),
     };
-    let mut aux = vec![0u64; data.len()];  // TODO: MaybeUninit
-    let mut from = &mut data[..];
-    let mut to = &mut aux[..];
+    drop(hash_phase);
+    // Safety: `compute_counts` hashed every word into `data`.
+    let mut from = unsafe { data.assume_init_mut() };
+    let mut to = aux;
     // Now do passes. Non-last passes just do dealing.
-    for pass in 0..passes - 1 {
+    for (pass, pass_counts) in counts.iter().enumerate().take(passes - 1) {
+        let _phase = phase_timing::indexed_phase("dlsd_and_count: scatter pass", pass);
         let mut heads = [0usize; RADIX];
         let mut pos = 0;
         for i in 0..RADIX {
             heads[i] = pos;
-            pos += counts[pass][i];
+            pos += pass_counts[i];
         }
 
-        for chunk in from.as_chunks::<CHUNK_SIZE>().0 {
-            for word in chunk {
-                let radix = read_radix(*word, pass, passes);
-                unsafe {
-                    let pos = heads.get_unchecked_mut(radix);
-                    *to.get_unchecked_mut(*pos) = *word;
-                    *pos += 1;
-                }
+        let mut deal = |word: u64| {
+            let radix = read_radix(word, pass, passes, last_pass_radix);
+            unsafe {
+                let pos = heads.get_unchecked_mut(radix);
+                to.get_unchecked_mut(*pos).write(word);
+                *pos += 1;
             }
+        };
+        let (chunks, remainder) = from.as_chunks::<CHUNK_SIZE>();
+        for chunk in chunks {
+            for &word in chunk {
+                deal(word);
+            }
+        }
+        for &word in remainder {
+            deal(word);
         }
-        std::mem::swap(&mut from, &mut to);
+        // Safety: the counts add up to the length, so the pass wrote every word of `to`. The next pass writes `from`
+        // only through `MaybeUninit::write`, with words it read.
+        (from, to) = (unsafe { to.assume_init_mut() }, unsafe { as_uninit_mut(from) });
     }
-
-    // Last pass does dealing and fused insertion sort and counting.
-    let pass = passes - 1;
-    #[derive(Clone, Copy)]
-    struct Head {
-        start: usize,
-        pos: usize,
+    LastPass {
+        from,
+        to,
+        counts: counts[passes - 1],
+        sum_of_radixes,
+        last_pass_radix,
     }
-    let mut heads = [Head { start: 0, pos: 0 }; RADIX];
+}
+
+/// A last-pass group: the words with one value of the last pass's digit.
+#[derive(Clone, Copy)]
+struct Head {
+    start: usize,
+    pos: usize,
+    shifts_left: usize,
+    fallback: bool,
+}
+
+/// Returns the last pass's groups, laid out back to back by `counts`, each with its insertion-sort budget.
+fn last_pass_heads(counts: &[usize; RADIX]) -> [Head; RADIX] {
+    let mut heads = [Head { start: 0, pos: 0, shifts_left: 0, fallback: false }; RADIX];
     let mut pos = 0;
     for i in 0..RADIX {
         heads[i] = Head {
             start: pos,
-            pos: pos,
+            pos,
+            shifts_left: counts[i] * INSERTION_SHIFTS_PER_WORD,
+            fallback: false,
         };
-        pos += counts[pass][i];
+        pos += counts[i];
     }
-    let sorted_bits_mask = (1u64 << (WORD_BITS - (passes as u32 * LG_RADIX))).wrapping_neg();
-    let mut unique_count = 0;
-    for chunk in from.as_chunks::<CHUNK_SIZE>().0 {
-        for &word in chunk {
-            let radix = read_last_pass_radix(word, last_pass_radix);
-            let head = unsafe { heads.get_unchecked_mut(radix) };
-            // Insertion sort backwards towards the beginning of the group.
-            let mut j = head.pos;
-            while j > head.start && unsafe { *to.get_unchecked(j - 1) } > word {
-                unsafe { *to.get_unchecked_mut(j) = *to.get_unchecked(j - 1) };
-                j -= 1;
-            }
-            unsafe { *to.get_unchecked_mut(j) = word };
-            if j > head.start {
-                let prev_word = unsafe { *to.get_unchecked(j - 1) };
-                unique_count += (prev_word < word) as usize;
-                if (prev_word & sorted_bits_mask) != (word & sorted_bits_mask) {
-                    // Stay in cache: once we've finished with a group, reset back to the beginning of the group.
-                    //
-                    // This is because we don't actually care about sorted order: we just care about the count.
-                    head.pos = head.start;
-                }
-            } else {
-                unique_count += 1;
-            }
-            head.pos += 1;
-        }
-    }
-    unique_count
+    heads
 }
 
-fn compute_counts<const PASSES: usize, Hasher: StatelessU64Hasher>(
+fn compute_counts<const PASSES: usize, Hasher: SeededU64Hasher>(
     orig_data: &[u64],
+    hasher: Hasher,
+    data: &mut [MaybeUninit<u64>],
     last_pass_radix: u32,
-) -> (Vec<u64>, [[usize; RADIX]; MAX_PASSES]) {
+) -> [[usize; RADIX]; MAX_PASSES] {
     let mut counts = [[0; RADIX]; MAX_PASSES];
-    let mut data = Vec::with_capacity(orig_data.len());
-    data.extend(orig_data
-        .as_chunks::<CHUNK_SIZE>().0
-        .iter()
-        .flat_map(|chunk| {
-            chunk.map(|word| {
-                let h = Hasher::hash(word);
-                for pass in 0..PASSES - 1 {
-                    let radix = read_radix(h, pass, PASSES);
-                    unsafe {
-                        *counts.get_unchecked_mut(pass).get_unchecked_mut(radix) += 1;
-                    }
-                }
-                let radix = read_last_pass_radix(h, last_pass_radix);
+    let mut hashes = [0u64; HASH_BLOCK];
+    // Hash a block at a time into `hashes`, then histogram it and copy it out while it's still in L1.
+    for (block, data_block) in orig_data.chunks(HASH_BLOCK).zip(data.chunks_mut(HASH_BLOCK)) {
+        let hashes = &mut hashes[..block.len()];
+        hasher.hash_batch(block, hashes);
+        data_block.write_copy_of_slice(hashes);
+        for &h in hashes.iter() {
+            for pass in 0..PASSES - 1 {
+                let radix = read_radix(h, pass, PASSES, last_pass_radix);
                 unsafe {
-                    *counts.get_unchecked_mut(PASSES - 1).get_unchecked_mut(radix) += 1;
+                    *counts.get_unchecked_mut(pass).get_unchecked_mut(radix) += 1;
                 }
-                h
-            })
-        }));
-    (data, counts)
+            }
+            let radix = read_last_pass_radix(h, last_pass_radix);
+            unsafe {
+                *counts.get_unchecked_mut(PASSES - 1).get_unchecked_mut(radix) += 1;
+            }
+        }
+    }
+    counts
 }
 
+/// Non-last passes read the `LG_RADIX`-bit digits directly below the last pass's digit, so that after all passes the
+/// top `sum_of_radixes` bits are sorted.
 #[inline(always)]
-fn read_radix(word: u64, pass: usize, passes: usize) -> usize {
+fn read_radix(word: u64, pass: usize, passes: usize, last_pass_radix: u32) -> usize {
     const MASK: u64 = (1 << LG_RADIX) - 1;
-    let shift = WORD_BITS - ((passes - pass) as u32 * LG_RADIX);
+    let shift = WORD_BITS - last_pass_radix - ((passes - 1 - pass) as u32 * LG_RADIX);
     ((word >> shift) & MASK) as usize
 }
 
 fn read_last_pass_radix(word: u64, last_pass_radix: u32) -> usize {
     (word >> (WORD_BITS - last_pass_radix)) as usize
-}
\ No newline at end of file
+}
+
+/// Below this size, a bucket is counted with `sort_unstable` rather than with radix passes.
+const SMALL_BUCKET: usize = 64;
+
+/// Memory-lean version of `dlsd_sort_and_count`.
+///
+/// Instead of dealing between two full-size buffers, we partition the hashed copy in place by its top `LG_RADIX`
+/// bits (American flag sort), and then run the fused sort-and-count on each bucket separately. The auxiliary buffer
+/// only needs to be as large as the largest bucket, so peak extra memory is about `(1 + 1/RADIX)` times the input
+/// rather than `2x`.
+pub fn dlsd_sort_and_count_lean<Hasher: SeededU64Hasher>(orig_data: &[u64], hasher: Hasher) -> usize {
+    dlsd_sort_and_count_lean_with_scratch(orig_data, hasher, &mut uninit_words(orig_data.len()), &mut Vec::new())
+}
+
+/// Like `dlsd_sort_and_count_lean`, but uses the caller's `data` and `aux` as scratch space.
+///
+/// `data` must be the same length as `orig_data`. `aux` is grown to the largest bucket if it is smaller, so reusing it
+/// across calls only allocates when a larger bucket comes along. Both may be uninitialized, and their contents on
+/// entry are ignored.
+pub fn dlsd_sort_and_count_lean_with_scratch<Hasher: SeededU64Hasher>(
+    orig_data: &[u64],
+    hasher: Hasher,
+    data: &mut [MaybeUninit<u64>],
+    aux: &mut Vec<MaybeUninit<u64>>,
+) -> usize {
+    assert!(data.len() == orig_data.len());
+    // Hash and histogram the top bits.
+    let mut counts = [0usize; RADIX];
+    let mut hashes = [0u64; HASH_BLOCK];
+    for (block, data_block) in orig_data.chunks(HASH_BLOCK).zip(data.chunks_mut(HASH_BLOCK)) {
+        let hashes = &mut hashes[..block.len()];
+        hasher.hash_batch(block, hashes);
+        data_block.write_copy_of_slice(hashes);
+        for &h in hashes.iter() {
+            unsafe {
+                *counts.get_unchecked_mut(read_last_pass_radix(h, LG_RADIX)) += 1;
+            }
+        }
+    }
+    // Safety: every word was hashed into `data` above.
+    let data = unsafe { data.assume_init_mut() };
+
+    // American flag sort, as in `msd_sort`.
+    let bucket_starts = partition(data, &counts, |word| read_last_pass_radix(word, LG_RADIX));
+
+    // Count each bucket independently. Buckets have disjoint top bits, so their unique counts add up.
+    grow(aux, counts.iter().copied().max().unwrap_or(0));
+    let mut unique_count = 0;
+    for bucket in 0..RADIX {
+        let bucket_data = &mut data[bucket_starts[bucket]..bucket_starts[bucket] + counts[bucket]];
+        unique_count += sort_and_count_bucket(bucket_data, aux, LG_RADIX);
+    }
+    unique_count
+}
+
+/// Fused sort-and-count of a bucket of hashes whose top `known_bits` bits are all equal.
+///
+/// Shifts those bits out first (which preserves distinctness within the bucket), so that the radix passes see the
+/// remaining entropy in the top bits just like `dlsd_sort_and_count` does.
+fn sort_and_count_bucket(data: &mut [u64], aux: &mut [MaybeUninit<u64>], known_bits: u32) -> usize {
+    if data.len() <= SMALL_BUCKET {
+        data.sort_unstable();
+        return count_unique_in_sorted(data);
+    }
+    let sum_of_radixes = data.len().next_power_of_two().ilog2();
+    let passes = sum_of_radixes.div_ceil(LG_RADIX) as usize;
+    let last_pass_radix = sum_of_radixes - (passes as u32 - 1) * LG_RADIX;
+
+    // On the stack rather than a `vec!`, which would allocate once per bucket.
+    let mut counts = [[0usize; RADIX]; MAX_PASSES];
+    let counts = &mut counts[..passes];
+    for word in data.iter_mut() {
+        *word <<= known_bits;
+        for pass in 0..passes - 1 {
+            let radix = read_radix(*word, pass, passes, last_pass_radix);
+            unsafe {
+                *counts.get_unchecked_mut(pass).get_unchecked_mut(radix) += 1;
+            }
+        }
+        let radix = read_last_pass_radix(*word, last_pass_radix);
+        unsafe {
+            *counts.get_unchecked_mut(passes - 1).get_unchecked_mut(radix) += 1;
+        }
+    }
+
+    let mut from = data;
+    let mut to = &mut aux[..from.len()];
+    // Non-last passes just do dealing.
+    for (pass, pass_counts) in counts.iter().enumerate().take(passes - 1) {
+        let mut heads = [0usize; RADIX];
+        let mut pos = 0;
+        for i in 0..RADIX {
+            heads[i] = pos;
+            pos += pass_counts[i];
+        }
+
+        for &word in from.iter() {
+            let radix = read_radix(word, pass, passes, last_pass_radix);
+            unsafe {
+                let pos = heads.get_unchecked_mut(radix);
+                to.get_unchecked_mut(*pos).write(word);
+                *pos += 1;
+            }
+        }
+        // Safety: as in `hash_and_scatter`, the pass wrote every word of `to`, and the next pass writes only words it
+        // read to `from`.
+        (from, to) = (unsafe { to.assume_init_mut() }, unsafe { as_uninit_mut(from) });
+    }
+
+    // Last pass does dealing and fused insertion sort and counting, as in `dlsd_sort_and_count`.
+    deal_and_count(from, to, &counts[passes - 1], sum_of_radixes, last_pass_radix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count_occurrences::run_lengths;
+    use crate::hashers::{MulSwapMulHasher, NoopHasher};
+
+    fn distinct(data: &[u64]) -> usize {
+        let mut sorted = data.to_vec();
+        sorted.sort_unstable();
+        count_unique_in_sorted(&sorted)
+    }
+
+    /// Checks the fused run-length pass against `run_lengths` of the sorted hashes, ignoring the order of the runs.
+    fn check_occurrences<H: SeededU64Hasher>(data: &[u64], hasher: H, context: &str) {
+        let mut hashes: Vec<u64> = data.iter().map(|&word| hasher.hash(word)).collect();
+        hashes.sort_unstable();
+        let mut occurrences = dlsd_sort_and_count_occurrences(data, hasher);
+        occurrences.sort_unstable();
+        assert_eq!(occurrences, run_lengths(&hashes), "{context}");
+    }
+
+    #[test]
+    fn counts_are_exact() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        // Up to three passes, and buckets of the lean version both below and above `SMALL_BUCKET`.
+        for len in [0, 1, 2, 3, 1000, 1024, 1025, 100_000, (1 << 20) + 1] {
+            // From all equal to all distinct. Small domains fill every group with runs of duplicates, which the old
+            // group reset counted more than once.
+            for domain in [1, 2, 16, len as u64 / 64 + 1, len as u64 / 2 + 1, u64::MAX] {
+                let data: Vec<u64> = (0..len).map(|_| rng.u64(..domain)).collect();
+                let expected = distinct(&data);
+                assert_eq!(dlsd_sort_and_count(&data, MulSwapMulHasher), expected, "len {len}, domain {domain}");
+                assert_eq!(dlsd_sort_and_count_lean(&data, MulSwapMulHasher), expected, "len {len}, domain {domain}");
+                check_occurrences(&data, MulSwapMulHasher, &format!("len {len}, domain {domain}"));
+            }
+        }
+    }
+
+    #[test]
+    fn skewed_groups_fall_back() {
+        // As in `dlsd`'s test: unhashed low-bits and spread-out keys share their sorted bits, so their last-pass group
+        // blows its insertion budget, and is counted by `sort_unstable` at the end instead.
+        let masks = [(1 << 40) - 1, ((1 << 40) - 1) & 0x5555_5555_5555_5555, (1u64 << 20).wrapping_neg()];
+        let mut rng = fastrand::Rng::with_seed(2);
+        for mask in masks {
+            // Drawn from half as many keys, so the fallback group has duplicates to count.
+            let keys: Vec<u64> = (0..50_000).map(|_| rng.u64(..) & mask).collect();
+            let data: Vec<u64> = (0..100_000).map(|_| keys[rng.usize(..keys.len())]).collect();
+            let expected = distinct(&data);
+            assert_eq!(dlsd_sort_and_count(&data, NoopHasher), expected, "mask {mask:#x}");
+            assert_eq!(dlsd_sort_and_count_lean(&data, NoopHasher), expected, "mask {mask:#x}");
+            check_occurrences(&data, NoopHasher, &format!("mask {mask:#x}"));
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! External-memory merge sort, for key sets larger than RAM.
+//!
+//! Reads the input a memory budget's worth at a time, sorts each chunk with `wide_merge_sort`, and spills it to a
+//! temporary file as a sorted run. Then streams up to 256 runs at a time through the same tournament tree as the
+//! in-memory merge, reading each run a buffered block at a time. If there are more runs than that, earlier passes
+//! merge them into longer runs on disk first.
+//!
+//! Files hold words in little-endian order.
+
+use crate::uninit::uninit_words;
+use crate::wide_merge_sort::{MergeTree, wide_merge_sort_with_scratch};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of runs merged at once.
+const FAN_IN: usize = 256;
+const WORD_BYTES: usize = 8;
+/// Names tried for a run's temporary file before giving up, if the ones before are taken.
+const CREATE_ATTEMPTS: usize = 16;
+
+/// Sorts the words of `input` and writes them to `output`, using about `memory_budget` bytes of memory plus temporary
+/// files.
+pub fn external_sort(input: impl IntoIterator<Item = u64>, output: impl Write, memory_budget: usize) -> io::Result<()> {
+    let sizes = Sizes::new(memory_budget);
+    let runs = merge_down(spill_runs(input, &sizes, false)?, &sizes, false)?;
+    let mut output = BufWriter::with_capacity(sizes.block_words * WORD_BYTES, output);
+    merge(&runs, &sizes, |word| output.write_all(&word.to_le_bytes()))?;
+    output.flush()
+}
+
+/// Returns the number of unique words in `input`, using about `memory_budget` bytes of memory plus temporary files.
+///
+/// Like `external_sort`, but deduplicates each run before it is written, and counts rather than writes the final
+/// merge.
+pub fn external_count_unique(input: impl IntoIterator<Item = u64>, memory_budget: usize) -> io::Result<usize> {
+    let sizes = Sizes::new(memory_budget);
+    let runs = merge_down(spill_runs(input, &sizes, true)?, &sizes, true)?;
+    let mut unique_count = 0;
+    merge(&runs, &sizes, distinct(|_| {
+        unique_count += 1;
+        Ok(())
+    }))?;
+    Ok(unique_count)
+}
+
+/// How the memory budget is divided up.
+struct Sizes {
+    /// Words per run. Run formation holds a run plus `wide_merge_sort`'s auxiliary buffer.
+    run_words: usize,
+    /// Words per buffered block. Merging holds one block per input run plus one for the output.
+    block_words: usize,
+}
+
+impl Sizes {
+    fn new(memory_budget: usize) -> Self {
+        Self {
+            run_words: (memory_budget / (2 * WORD_BYTES)).max(1),
+            block_words: (memory_budget / ((FAN_IN + 1) * WORD_BYTES)).max(1),
+        }
+    }
+}
+
+/// Splits `input` into sorted runs on disk, deduplicating each one if `dedup` is set.
+fn spill_runs(input: impl IntoIterator<Item = u64>, sizes: &Sizes, dedup: bool) -> io::Result<Vec<Run>> {
+    let mut input = input.into_iter();
+    let mut data = Vec::with_capacity(sizes.run_words);
+    let mut aux = uninit_words(sizes.run_words);
+    let mut runs = Vec::new();
+    loop {
+        data.clear();
+        data.extend(input.by_ref().take(sizes.run_words));
+        if data.is_empty() {
+            return Ok(runs);
+        }
+        wide_merge_sort_with_scratch(&mut data, &mut aux);
+        if dedup {
+            data.dedup();
+        }
+        let mut writer = RunWriter::create(sizes)?;
+        for &word in &data {
+            writer.push(word)?;
+        }
+        runs.push(writer.finish()?);
+    }
+}
+
+/// Merges groups of runs into longer runs until at most `FAN_IN` are left.
+fn merge_down(mut runs: Vec<Run>, sizes: &Sizes, dedup: bool) -> io::Result<Vec<Run>> {
+    while runs.len() > FAN_IN {
+        runs = runs
+            .chunks(FAN_IN)
+            .map(|group| {
+                let mut writer = RunWriter::create(sizes)?;
+                if dedup {
+                    merge(group, sizes, distinct(|word| writer.push(word)))?;
+                } else {
+                    merge(group, sizes, |word| writer.push(word))?;
+                }
+                writer.finish()
+            })
+            .collect::<io::Result<_>>()?;
+    }
+    Ok(runs)
+}
+
+/// Merges up to `FAN_IN` runs, passing each word to `sink` in sorted order.
+fn merge(runs: &[Run], sizes: &Sizes, mut sink: impl FnMut(u64) -> io::Result<()>) -> io::Result<()> {
+    assert!(runs.len() <= FAN_IN);
+    let len: usize = runs.iter().map(|run| run.len).sum();
+    let mut readers = runs.iter().map(|run| run.reader(sizes)).collect::<io::Result<Vec<_>>>()?;
+    readers.resize_with(FAN_IN, RunReader::empty);
+    let Ok(readers) = <[RunReader; FAN_IN]>::try_from(readers) else {
+        unreachable!()
+    };
+
+    let mut tree = MergeTree::new(readers);
+    for i in 0..len {
+        let word = tree.pop();
+        if word == u64::MAX {
+            // Everything left is u64::MAX; see `MergeTree`.
+            for _ in i..len {
+                sink(u64::MAX)?;
+            }
+            break;
+        }
+        sink(word)?;
+    }
+    // A failed read ends its run early, which is only detected here.
+    for reader in tree.into_sources() {
+        if let Some(error) = reader.error {
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+/// Wraps `sink` to skip repeats of the previous word, so a sorted stream comes out deduplicated.
+fn distinct(mut sink: impl FnMut(u64) -> io::Result<()>) -> impl FnMut(u64) -> io::Result<()> {
+    let mut prev = None;
+    move |word| {
+        if prev == Some(word) {
+            return Ok(());
+        }
+        prev = Some(word);
+        sink(word)
+    }
+}
+
+/// A sorted run in a temporary file, which is deleted when the run is dropped.
+struct Run {
+    path: PathBuf,
+    len: usize,
+}
+
+impl Run {
+    fn reader(&self, sizes: &Sizes) -> io::Result<RunReader> {
+        Ok(RunReader {
+            file: Some(File::open(&self.path)?),
+            remaining: self.len,
+            block: Vec::with_capacity(sizes.block_words.min(self.len)),
+            pos: 0,
+            error: None,
+        })
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        _ = std::fs::remove_file(&self.path);
+    }
+}
+
+struct RunWriter {
+    run: Run,
+    file: BufWriter<File>,
+}
+
+impl RunWriter {
+    /// Creates a new temporary file for the run.
+    ///
+    /// The temporary directory is shared, so the name has a random part, and the file is only ever created, never
+    /// opened if it exists: a file or symlink someone else put there just makes us try another name.
+    fn create(sizes: &Sizes) -> io::Result<Self> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let mut attempts = 0;
+        let (path, file) = loop {
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+            let name = format!("hashed-sorting-run-{}-{id}-{:016x}", std::process::id(), fastrand::u64(..));
+            let path = std::env::temp_dir().join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists && attempts + 1 < CREATE_ATTEMPTS => {
+                    attempts += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        };
+        Ok(Self {
+            run: Run { path, len: 0 },
+            file: BufWriter::with_capacity(sizes.block_words * WORD_BYTES, file),
+        })
+    }
+
+    #[inline(always)]
+    fn push(&mut self, word: u64) -> io::Result<()> {
+        self.run.len += 1;
+        self.file.write_all(&word.to_le_bytes())
+    }
+
+    fn finish(mut self) -> io::Result<Run> {
+        self.file.flush()?;
+        Ok(self.run)
+    }
+}
+
+/// Streams the words of a run, a block at a time.
+///
+/// As an `Iterator` it can't return errors, so a failed read ends the run early and is kept in `error`.
+struct RunReader {
+    file: Option<File>,
+    /// Words not yet read from the file.
+    remaining: usize,
+    block: Vec<u64>,
+    pos: usize,
+    error: Option<io::Error>,
+}
+
+impl RunReader {
+    /// A reader of an empty run, to fill unused slots in the tournament tree.
+    fn empty() -> Self {
+        Self {
+            file: None,
+            remaining: 0,
+            block: Vec::new(),
+            pos: 0,
+            error: None,
+        }
+    }
+
+    #[inline(never)]
+    fn refill(&mut self) -> bool {
+        let Some(file) = &mut self.file else {
+            return false;
+        };
+        let len = self.remaining.min(self.block.capacity());
+        if len == 0 {
+            return false;
+        }
+        self.block.resize(len, 0);
+        // Read straight into the block, then fix up the byte order.
+        let bytes = unsafe { std::slice::from_raw_parts_mut(self.block.as_mut_ptr() as *mut u8, len * WORD_BYTES) };
+        if let Err(error) = file.read_exact(bytes) {
+            self.error = Some(error);
+            self.remaining = 0;
+            self.block.clear();
+            self.pos = 0;
+            return false;
+        }
+        for word in &mut self.block {
+            *word = u64::from_le(*word);
+        }
+        self.remaining -= len;
+        self.pos = 0;
+        true
+    }
+}
+
+impl Iterator for RunReader {
+    type Item = u64;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<u64> {
+        if self.pos == self.block.len() && !self.refill() {
+            return None;
+        }
+        let word = self.block[self.pos];
+        self.pos += 1;
+        Some(word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Small enough that runs are 8 words and merge blocks 1 word, so more than 2048 words make more than `FAN_IN`
+    /// runs, which are merged on disk by `merge_down` before the final merge.
+    const TINY_BUDGET: usize = 128;
+
+    fn sorted_externally(data: &[u64], memory_budget: usize) -> Vec<u64> {
+        let mut output = Vec::new();
+        external_sort(data.iter().copied(), &mut output, memory_budget).unwrap();
+        output.chunks_exact(WORD_BYTES).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap())).collect()
+    }
+
+    fn check(data: &[u64], memory_budget: usize) {
+        let mut expected = data.to_vec();
+        expected.sort_unstable();
+        assert_eq!(sorted_externally(data, memory_budget), expected, "len {}", data.len());
+        expected.dedup();
+        let unique_count = external_count_unique(data.iter().copied(), memory_budget).unwrap();
+        assert_eq!(unique_count, expected.len(), "len {}", data.len());
+    }
+
+    #[test]
+    fn empty() {
+        check(&[], TINY_BUDGET);
+        check(&[], 1 << 20);
+    }
+
+    #[test]
+    fn matches_sort_unstable() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        for len in [1, 7, 8, 9, 1000, 2048, 2049, 5000] {
+            check(&(0..len).map(|_| rng.u64(..)).collect::<Vec<_>>(), TINY_BUDGET);
+            check(&(0..len).map(|_| rng.u64(..100)).collect::<Vec<_>>(), TINY_BUDGET);
+            // Runs ending in u64::MAX, and the final merge's early stop.
+            check(&(0..len).map(|_| u64::MAX - rng.u64(..3)).collect::<Vec<_>>(), TINY_BUDGET);
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Vectorized batch hashing for the multiply-based hashers, hashing a vector of words at a time.
+//!
+//! A hasher's batch version is written once, against the lane operations in `lanes`, which are selected at compile
+//! time like `small_sort`'s: AVX-512 (with DQ and BW, for 64-bit multiplies and byte shuffles) hashes 8 words at a
+//! time and AVX2 4, while the fallback is plain `u64` arithmetic, one word at a time. AVX2 has no 64-bit multiply, so
+//! it builds one from three 32-bit multiplies.
+
+pub use lanes::Vector;
+use lanes::*;
+
+/// Sets `hashes[i] = scalar(values[i])`, using `vector` to hash a whole vector of words at a time and `scalar` for
+/// the leftover words. The two must compute the same function.
+#[inline(always)]
+pub fn map(values: &[u64], hashes: &mut [u64], vector: impl Fn(Vector) -> Vector, scalar: impl Fn(u64) -> u64) {
+    assert!(values.len() == hashes.len());
+    unsafe { map_raw(values.as_ptr(), hashes.as_mut_ptr(), values.len(), vector, scalar) }
+}
+
+/// Like `map`, but replaces each word with its hash.
+#[inline(always)]
+pub fn map_in_place(values: &mut [u64], vector: impl Fn(Vector) -> Vector, scalar: impl Fn(u64) -> u64) {
+    // One pointer for both: deriving a second one from `values` would invalidate the first.
+    let (ptr, len) = (values.as_mut_ptr(), values.len());
+    unsafe { map_raw(ptr as *const u64, ptr, len, vector, scalar) }
+}
+
+/// `map` on `len` words at `src`, writing to `dst`. `src` and `dst` may be the same: each word is read before it is
+/// written.
+#[inline(always)]
+unsafe fn map_raw(
+    src: *const u64,
+    dst: *mut u64,
+    len: usize,
+    vector: impl Fn(Vector) -> Vector,
+    scalar: impl Fn(u64) -> u64,
+) {
+    let full_len = len / LANES * LANES;
+    unsafe {
+        for i in (0..full_len).step_by(LANES) {
+            store(dst.add(i), vector(load(src.add(i))));
+        }
+        for i in full_len..len {
+            *dst.add(i) = scalar(*src.add(i));
+        }
+    }
+}
+
+/// `MulSwapMulHasher`'s multiply, byte swap, multiply, with the given multipliers.
+#[inline(always)]
+pub fn mul_swap_mul(multipliers: [u64; 2]) -> impl Fn(Vector) -> Vector {
+    let [m0, m1] = multipliers.map(splat);
+    move |v| mul(swap_bytes(mul(v, m0)), m1)
+}
+
+/// A Murmur-style finalizer: xor in `seed`, then xorshift, multiply, xorshift, multiply, xorshift.
+#[inline(always)]
+pub fn xorshift_multiply(seed: u64, shifts: [u32; 3], multipliers: [u64; 2]) -> impl Fn(Vector) -> Vector {
+    let seed = splat(seed);
+    let [m0, m1] = multipliers.map(splat);
+    move |v| {
+        let mut h = xor(v, seed);
+        h = xor(h, shr(h, shifts[0]));
+        h = mul(h, m0);
+        h = xor(h, shr(h, shifts[1]));
+        h = mul(h, m1);
+        xor(h, shr(h, shifts[2]))
+    }
+}
+
+/// `RrmxmxHasher`'s rotate-xor step followed by two rounds of multiply and xorshift.
+#[inline(always)]
+pub fn rrmxmx(multiplier: u64) -> impl Fn(Vector) -> Vector {
+    let m = splat(multiplier);
+    move |v| {
+        let mut h = xor(v, xor(rotate_right(v, 49), rotate_right(v, 24)));
+        h = mul(h, m);
+        h = xor(h, shr(h, 28));
+        h = mul(h, m);
+        xor(h, shr(h, 28))
+    }
+}
+
+#[cfg(all(
+    target_arch = "x86_64",
+    target_feature = "avx512f",
+    target_feature = "avx512dq",
+    target_feature = "avx512bw"
+))]
+mod lanes {
+    use std::arch::x86_64::*;
+
+    pub type Vector = __m512i;
+    pub const LANES: usize = 8;
+
+    #[inline(always)]
+    pub unsafe fn load(ptr: *const u64) -> Vector {
+        unsafe { _mm512_loadu_si512(ptr as *const __m512i) }
+    }
+
+    #[inline(always)]
+    pub unsafe fn store(ptr: *mut u64, v: Vector) {
+        unsafe { _mm512_storeu_si512(ptr as *mut __m512i, v) }
+    }
+
+    #[inline(always)]
+    pub fn splat(word: u64) -> Vector {
+        unsafe { _mm512_set1_epi64(word as i64) }
+    }
+
+    #[inline(always)]
+    pub fn xor(a: Vector, b: Vector) -> Vector {
+        unsafe { _mm512_xor_si512(a, b) }
+    }
+
+    #[inline(always)]
+    pub fn shr(v: Vector, shift: u32) -> Vector {
+        unsafe { _mm512_srl_epi64(v, _mm_cvtsi32_si128(shift as i32)) }
+    }
+
+    #[inline(always)]
+    pub fn rotate_right(v: Vector, shift: u32) -> Vector {
+        unsafe { _mm512_rorv_epi64(v, splat(shift as u64)) }
+    }
+
+    #[inline(always)]
+    pub fn mul(a: Vector, b: Vector) -> Vector {
+        unsafe { _mm512_mullo_epi64(a, b) }
+    }
+
+    #[inline(always)]
+    pub fn swap_bytes(v: Vector) -> Vector {
+        // Reverses the bytes of each word; the shuffle indexes within 128-bit lanes.
+        unsafe {
+            let reversed = _mm512_set_epi64(
+                0x08090a0b0c0d0e0f,
+                0x0001020304050607,
+                0x08090a0b0c0d0e0f,
+                0x0001020304050607,
+                0x08090a0b0c0d0e0f,
+                0x0001020304050607,
+                0x08090a0b0c0d0e0f,
+                0x0001020304050607,
+            );
+            _mm512_shuffle_epi8(v, reversed)
+        }
+    }
+}
+
+#[cfg(all(
+    target_arch = "x86_64",
+    target_feature = "avx2",
+    not(all(target_feature = "avx512f", target_feature = "avx512dq", target_feature = "avx512bw"))
+))]
+mod lanes {
+    use std::arch::x86_64::*;
+
+    pub type Vector = __m256i;
+    pub const LANES: usize = 4;
+
+    #[inline(always)]
+    pub unsafe fn load(ptr: *const u64) -> Vector {
+        unsafe { _mm256_loadu_si256(ptr as *const __m256i) }
+    }
+
+    #[inline(always)]
+    pub unsafe fn store(ptr: *mut u64, v: Vector) {
+        unsafe { _mm256_storeu_si256(ptr as *mut __m256i, v) }
+    }
+
+    #[inline(always)]
+    pub fn splat(word: u64) -> Vector {
+        unsafe { _mm256_set1_epi64x(word as i64) }
+    }
+
+    #[inline(always)]
+    pub fn xor(a: Vector, b: Vector) -> Vector {
+        unsafe { _mm256_xor_si256(a, b) }
+    }
+
+    #[inline(always)]
+    pub fn shr(v: Vector, shift: u32) -> Vector {
+        unsafe { _mm256_srl_epi64(v, _mm_cvtsi32_si128(shift as i32)) }
+    }
+
+    #[inline(always)]
+    pub fn rotate_right(v: Vector, shift: u32) -> Vector {
+        unsafe { _mm256_or_si256(shr(v, shift), _mm256_sll_epi64(v, _mm_cvtsi32_si128(64 - shift as i32))) }
+    }
+
+    #[inline(always)]
+    pub fn mul(a: Vector, b: Vector) -> Vector {
+        // The low word of the product: low halves multiplied, plus both cross products shifted up. The high halves'
+        // product only affects bits above the word.
+        unsafe {
+            let low = _mm256_mul_epu32(a, b);
+            let cross = _mm256_add_epi64(
+                _mm256_mul_epu32(_mm256_srli_epi64(a, 32), b),
+                _mm256_mul_epu32(a, _mm256_srli_epi64(b, 32)),
+            );
+            _mm256_add_epi64(low, _mm256_slli_epi64(cross, 32))
+        }
+    }
+
+    #[inline(always)]
+    pub fn swap_bytes(v: Vector) -> Vector {
+        // Reverses the bytes of each word; the shuffle indexes within 128-bit lanes.
+        unsafe {
+            let reversed =
+                _mm256_set_epi64x(0x08090a0b0c0d0e0f, 0x0001020304050607, 0x08090a0b0c0d0e0f, 0x0001020304050607);
+            _mm256_shuffle_epi8(v, reversed)
+        }
+    }
+}
+
+#[cfg(not(all(
+    target_arch = "x86_64",
+    any(
+        target_feature = "avx2",
+        all(target_feature = "avx512f", target_feature = "avx512dq", target_feature = "avx512bw")
+    )
+)))]
+mod lanes {
+    pub type Vector = u64;
+    pub const LANES: usize = 1;
+
+    #[inline(always)]
+    pub unsafe fn load(ptr: *const u64) -> Vector {
+        unsafe { *ptr }
+    }
+
+    #[inline(always)]
+    pub unsafe fn store(ptr: *mut u64, v: Vector) {
+        unsafe { *ptr = v }
+    }
+
+    #[inline(always)]
+    pub fn splat(word: u64) -> Vector {
+        word
+    }
+
+    #[inline(always)]
+    pub fn xor(a: Vector, b: Vector) -> Vector {
+        a ^ b
+    }
+
+    #[inline(always)]
+    pub fn shr(v: Vector, shift: u32) -> Vector {
+        v >> shift
+    }
+
+    #[inline(always)]
+    pub fn rotate_right(v: Vector, shift: u32) -> Vector {
+        v.rotate_right(shift)
+    }
+
+    #[inline(always)]
+    pub fn mul(a: Vector, b: Vector) -> Vector {
+        a.wrapping_mul(b)
+    }
+
+    #[inline(always)]
+    pub fn swap_bytes(v: Vector) -> Vector {
+        v.swap_bytes()
+    }
+}
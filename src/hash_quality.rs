@@ -0,0 +1,159 @@
+//! Statistical quality scores for `SeededU64Hasher`s, to weigh alongside their timings. `score` computes them for any
+//! hasher; the benchmark prints them for its own.
+//!
+//! - Avalanche: flipping any one key bit should flip each hash bit with probability 1/2. Scored by the worst
+//!   deviation from 1/2 over all (key bit, hash bit) pairs.
+//! - Bit independence: when a key bit flips, any two hash bits should flip independently of each other. Scored by the
+//!   worst correlation over all key bits and pairs of hash bits.
+//! - Buckets: chi-square of bucket indices of the distinct keys of each `MaskStyle`, both from the low hash bits,
+//!   which the hash tables index by, and from the top hash bits, which the radix sorts partition by. Scored as a
+//!   z-score, which for a random function is about normally distributed. Far above 3 means uneven buckets; far below
+//!   -3 means more even than random, which does no harm.
+
+use crate::hashers::SeededU64Hasher;
+use crate::workloads::{MaskStyle, random_keys};
+
+/// Random keys per key bit for the avalanche and bit independence tests.
+const LG_SAMPLES: usize = 14;
+const SAMPLES: usize = 1 << LG_SAMPLES;
+const WORD_BITS: usize = 64;
+/// Number of buckets for the chi-square tests, as many as one `dlsd` radix pass has.
+const LG_BUCKETS: u32 = 10;
+const BUCKETS: usize = 1 << LG_BUCKETS;
+/// Domain of the bucket test keys: about 64 distinct keys per bucket.
+const LG_DOMAIN_SIZE: usize = 16;
+/// One standard deviation of sampling noise in the bit independence score, and twice that in the avalanche score.
+pub const NOISE: f64 = 1.0 / (1 << (LG_SAMPLES / 2)) as f64;
+
+/// A hasher's scores. A random function scores within a few `NOISE` of 0 on the first two, and below about 3 on the
+/// z-scores.
+#[derive(Debug, Clone, Copy)]
+pub struct Scores {
+    /// Worst deviation from 1/2 of the probability that flipping a key bit flips a hash bit.
+    pub avalanche: f64,
+    /// Worst correlation between two hash bits flipping when a key bit flips.
+    pub bit_independence: f64,
+    /// Chi-square z-scores of buckets indexed by the low hash bits, for the keys of each of `MaskStyle::ALL`.
+    pub low_bucket_z: [f64; MaskStyle::ALL.len()],
+    /// Like `low_bucket_z`, for buckets indexed by the top hash bits.
+    pub top_bucket_z: [f64; MaskStyle::ALL.len()],
+}
+
+/// Scores `hasher`. Deterministic: the keys come from a fixed seed.
+pub fn score<H: SeededU64Hasher>(hasher: H) -> Scores {
+    let mut rng = fastrand::Rng::with_seed(0);
+    let (avalanche, bit_independence) = flip_scores(hasher, &mut rng);
+    let mut low_bucket_z = [0.0; MaskStyle::ALL.len()];
+    let mut top_bucket_z = [0.0; MaskStyle::ALL.len()];
+    for (i, mask_style) in MaskStyle::ALL.into_iter().enumerate() {
+        let mut keys = random_keys(&mut rng, 4 << LG_DOMAIN_SIZE, LG_DOMAIN_SIZE, mask_style);
+        keys.sort_unstable();
+        keys.dedup();
+        low_bucket_z[i] = chi_square_z(keys.iter().map(|&key| hasher.hash(key) as usize % BUCKETS));
+        top_bucket_z[i] = chi_square_z(keys.iter().map(|&key| (hasher.hash(key) >> (64 - LG_BUCKETS)) as usize));
+    }
+    Scores {
+        avalanche,
+        bit_independence,
+        low_bucket_z,
+        top_bucket_z,
+    }
+}
+
+/// Returns the avalanche and bit independence scores.
+fn flip_scores<H: SeededU64Hasher>(hasher: H, rng: &mut fastrand::Rng) -> (f64, f64) {
+    let keys: Vec<u64> = (0..SAMPLES).map(|_| rng.u64(..)).collect();
+    let hashes: Vec<u64> = keys.iter().map(|&key| hasher.hash(key)).collect();
+    let mut avalanche: f64 = 0.0;
+    let mut bit_independence: f64 = 0.0;
+    // flipped[j] has bit s set if hash bit j flipped for sample s.
+    let mut flipped = vec![[0u64; SAMPLES / WORD_BITS]; WORD_BITS];
+    for key_bit in 0..WORD_BITS {
+        for row in &mut flipped {
+            row.fill(0);
+        }
+        for (s, (&key, &hash)) in keys.iter().zip(&hashes).enumerate() {
+            let diff = hash ^ hasher.hash(key ^ (1 << key_bit));
+            for (j, row) in flipped.iter_mut().enumerate() {
+                row[s / WORD_BITS] |= ((diff >> j) & 1) << (s % WORD_BITS);
+            }
+        }
+        let flip_rates: Vec<f64> = flipped.iter().map(|row| count_ones(row) as f64 / SAMPLES as f64).collect();
+        for &rate in &flip_rates {
+            avalanche = avalanche.max((rate - 0.5).abs());
+        }
+        for j in 0..WORD_BITS {
+            for k in j + 1..WORD_BITS {
+                let both = flipped[j].iter().zip(&flipped[k]).map(|(a, b)| (a & b).count_ones() as usize).sum::<usize>();
+                let (p_j, p_k) = (flip_rates[j], flip_rates[k]);
+                let variance = p_j * (1.0 - p_j) * p_k * (1.0 - p_k);
+                // A hash bit that always or never flips isn't independent of anything.
+                let correlation =
+                    if variance == 0.0 { 1.0 } else { (both as f64 / SAMPLES as f64 - p_j * p_k) / variance.sqrt() };
+                bit_independence = bit_independence.max(correlation.abs());
+            }
+        }
+    }
+    (avalanche, bit_independence)
+}
+
+fn count_ones(row: &[u64]) -> usize {
+    row.iter().map(|word| word.count_ones() as usize).sum()
+}
+
+/// Returns the chi-square statistic of `buckets` against a uniform distribution over `BUCKETS` buckets, normalized
+/// to a z-score.
+fn chi_square_z(buckets: impl Iterator<Item = usize>) -> f64 {
+    let mut counts = [0usize; BUCKETS];
+    for bucket in buckets {
+        counts[bucket] += 1;
+    }
+    let expected = counts.iter().sum::<usize>() as f64 / BUCKETS as f64;
+    let chi_square: f64 = counts.iter().map(|&count| (count as f64 - expected).powi(2) / expected).sum();
+    let degrees_of_freedom = (BUCKETS - 1) as f64;
+    (chi_square - degrees_of_freedom) / (2.0 * degrees_of_freedom).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashers::{MoremurHasher, MurmurHasher, NoopHasher, WyHasher};
+
+    /// Allowed deviation, in standard deviations of sampling noise, of the worst of many roughly normal samples.
+    const SIGMAS: f64 = 6.0;
+
+    #[test]
+    fn noop_scores_badly() {
+        let scores = score(NoopHasher);
+        // Flipping a key bit flips exactly that hash bit.
+        assert_eq!(scores.avalanche, 0.5);
+        assert_eq!(scores.bit_independence, 1.0);
+        // High-bits keys all land in low-bit bucket 0, and low-bits keys in top-bit bucket 0.
+        assert!(scores.low_bucket_z.iter().any(|&z| z > 100.0), "{:?}", scores.low_bucket_z);
+        assert!(scores.top_bucket_z.iter().any(|&z| z > 100.0), "{:?}", scores.top_bucket_z);
+    }
+
+    fn assert_avalanche_and_buckets_pass(scores: &Scores, name: &str) {
+        assert!(scores.avalanche < SIGMAS * NOISE / 2.0, "{name}: avalanche {}", scores.avalanche);
+        for z in scores.low_bucket_z.iter().chain(&scores.top_bucket_z) {
+            assert!(z.abs() < SIGMAS, "{name}: low {:?}, top {:?}", scores.low_bucket_z, scores.top_bucket_z);
+        }
+    }
+
+    #[test]
+    fn good_hashers_pass() {
+        for (name, scores) in [("WyHasher", score(WyHasher)), ("MoremurHasher", score(MoremurHasher))] {
+            assert_avalanche_and_buckets_pass(&scores, name);
+            assert!(scores.bit_independence < SIGMAS * NOISE, "{name}: bit independence {}", scores.bit_independence);
+        }
+    }
+
+    #[test]
+    fn murmur_fails_only_bit_independence() {
+        let scores = score(MurmurHasher);
+        assert_avalanche_and_buckets_pass(&scores, "MurmurHasher");
+        // Flipping key bit 0 flips hash bits 1 and 34 together almost every time: the final `h ^= h >> 33` xors bit 34
+        // into bit 1, and bit 1 itself rarely flips before it.
+        assert!(scores.bit_independence > 0.5, "bit independence {}", scores.bit_independence);
+    }
+}
@@ -0,0 +1,45 @@
+//! Prints the `hash_quality` scores of every hasher, to weigh alongside their timings.
+
+use hashed_sorting_benchmark::hash_quality::{NOISE, score};
+use hashed_sorting_benchmark::hashers::{
+    AesHasher, Crc32cHasher, MoremurHasher, MulSwapMulHasher, MurmurHasher, NoopHasher, RrmxmxHasher,
+    SeededMulSwapMulHasher, SeededMurmurHasher, SeededU64Hasher, WyHasher,
+};
+use hashed_sorting_benchmark::workloads::MaskStyle;
+
+/// Scores every hasher and prints the results.
+pub fn print_report() {
+    println!(
+        "hash quality (avalanche bias, noise {:.4}; bit independence correlation, noise {:.4}; bucket chi-square z-scores \
+         for {:?}):",
+        NOISE / 2.0,
+        NOISE,
+        MaskStyle::ALL
+    );
+    print_scores("NoOp", NoopHasher);
+    print_scores("Murmur", MurmurHasher);
+    print_scores("MulSwapMul", MulSwapMulHasher);
+    print_scores("seeded Murmur", SeededMurmurHasher::random());
+    print_scores("seeded MulSwapMul", SeededMulSwapMulHasher::random());
+    print_scores("WyHash", WyHasher);
+    print_scores("Moremur", MoremurHasher);
+    print_scores("rrmxmx", RrmxmxHasher);
+    if Crc32cHasher::is_supported() {
+        print_scores("CRC32C", Crc32cHasher);
+    }
+    if AesHasher::is_supported() {
+        print_scores("AES", AesHasher);
+    }
+}
+
+fn print_scores<H: SeededU64Hasher>(name: &str, hasher: H) {
+    let scores = score(hasher);
+    let z_scores = |zs: &[f64]| zs.iter().map(|z| format!("{z:.1}")).collect::<Vec<_>>().join(" / ");
+    println!(
+        "  {name}: avalanche {:.4}, bit independence {:.4}, low-bit buckets {}, top-bit buckets {}",
+        scores.avalanche,
+        scores.bit_independence,
+        z_scores(&scores.low_bucket_z),
+        z_scores(&scores.top_bucket_z),
+    );
+}
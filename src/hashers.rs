@@ -1,7 +1,97 @@
+use crate::hash_batch;
+
 pub trait StatelessU64Hasher {
     fn hash(value: u64) -> u64;
+
+    /// Hashes each of `values` into `hashes`, which must be the same length. Hashers with a vectorized version (see
+    /// `hash_batch`) override this.
+    #[inline(always)]
+    fn hash_batch(values: &[u64], hashes: &mut [u64]) {
+        assert!(values.len() == hashes.len());
+        for (h, &value) in hashes.iter_mut().zip(values) {
+            *h = Self::hash(value);
+        }
+    }
+
+    /// Like `hash_batch`, but replaces each value with its hash.
+    #[inline(always)]
+    fn hash_batch_in_place(values: &mut [u64]) {
+        for value in values {
+            *value = Self::hash(*value);
+        }
+    }
+}
+
+/// A hasher that is passed around by value, so it can carry a seed. The hash-based algorithms take one of these.
+///
+/// Every `StatelessU64Hasher` is one, with no seed. A fixed hash function lets anyone who knows it pick keys that
+/// collide, forcing worst-case probing in a hash table; a seeded hasher with a random seed doesn't.
+pub trait SeededU64Hasher: Copy {
+    fn hash(&self, value: u64) -> u64;
+
+    /// See `StatelessU64Hasher::hash_batch`.
+    #[inline(always)]
+    fn hash_batch(&self, values: &[u64], hashes: &mut [u64]) {
+        assert!(values.len() == hashes.len());
+        for (h, &value) in hashes.iter_mut().zip(values) {
+            *h = self.hash(value);
+        }
+    }
+
+    /// See `StatelessU64Hasher::hash_batch_in_place`.
+    #[inline(always)]
+    fn hash_batch_in_place(&self, values: &mut [u64]) {
+        for value in values {
+            *value = self.hash(*value);
+        }
+    }
+}
+
+impl<H: StatelessU64Hasher + Copy> SeededU64Hasher for H {
+    #[inline(always)]
+    fn hash(&self, value: u64) -> u64 {
+        <H as StatelessU64Hasher>::hash(value)
+    }
+
+    #[inline(always)]
+    fn hash_batch(&self, values: &[u64], hashes: &mut [u64]) {
+        <H as StatelessU64Hasher>::hash_batch(values, hashes)
+    }
+
+    #[inline(always)]
+    fn hash_batch_in_place(&self, values: &mut [u64]) {
+        <H as StatelessU64Hasher>::hash_batch_in_place(values)
+    }
+}
+
+/// A hasher that is a bijection on u64s, so a hash can be mapped back to its key.
+///
+/// Hashed sorting sorts and deduplicates hashes rather than keys; with one of these it can hand back the distinct keys
+/// themselves.
+pub trait InvertibleU64Hasher: SeededU64Hasher {
+    fn unhash(&self, hash: u64) -> u64;
 }
 
+/// Returns the inverse of odd `a` modulo 2^64, i.e. the multiplier that undoes `wrapping_mul(a)`.
+const fn inverse_mul(a: u64) -> u64 {
+    assert!(a % 2 == 1, "only odd multipliers are invertible");
+    // Newton's iteration. `a` is its own inverse modulo 8, so we start with 3 correct bits, and each step doubles them.
+    let mut x = a;
+    let mut i = 0;
+    while i < 5 {
+        x = x.wrapping_mul(2u64.wrapping_sub(a.wrapping_mul(x)));
+        i += 1;
+    }
+    x
+}
+
+/// A random seed, different on every run.
+fn random_seed() -> u64 {
+    // fastrand's global generator is seeded from the OS on first use.
+    fastrand::u64(..)
+}
+
+#[derive(Clone, Copy, Default)]
 pub struct NoopHasher;
 
 impl StatelessU64Hasher for NoopHasher {
@@ -9,19 +99,52 @@ impl StatelessU64Hasher for NoopHasher {
     fn hash(value: u64) -> u64 {
         value
     }
+
+    #[inline(always)]
+    fn hash_batch(values: &[u64], hashes: &mut [u64]) {
+        hashes.copy_from_slice(values);
+    }
+
+    #[inline(always)]
+    fn hash_batch_in_place(_values: &mut [u64]) {}
+}
+
+impl InvertibleU64Hasher for NoopHasher {
+    #[inline(always)]
+    fn unhash(&self, hash: u64) -> u64 {
+        hash
+    }
 }
 
+#[derive(Clone, Copy, Default)]
 pub struct MurmurHasher;
 
 impl MurmurHasher {
+    const MULTIPLIERS: [u64; 2] = [0xff51afd7ed558ccd, 0xc4ceb9fe1a85ec53];
+    const INVERSE_MULTIPLIERS: [u64; 2] = [inverse_mul(Self::MULTIPLIERS[0]), inverse_mul(Self::MULTIPLIERS[1])];
+
     #[inline(always)]
     pub fn hash_u64(value: u64) -> u64 {
         // MurmurHash3 64-bit finalizer
         let mut h = value;
         h ^= h >> 33;
-        h = h.wrapping_mul(0xff51afd7ed558ccd);
+        h = h.wrapping_mul(Self::MULTIPLIERS[0]);
         h ^= h >> 33;
-        h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+        h = h.wrapping_mul(Self::MULTIPLIERS[1]);
+        h ^= h >> 33;
+        h
+    }
+
+    /// Inverse of `hash_u64`.
+    #[inline(always)]
+    pub fn unhash_u64(hash: u64) -> u64 {
+        // A shift by at least half the word is its own inverse: the second xor cancels the first, since the bits it
+        // shifts in are the top ones, which the first left alone.
+        let mut h = hash;
+        h ^= h >> 33;
+        h = h.wrapping_mul(Self::INVERSE_MULTIPLIERS[1]);
+        h ^= h >> 33;
+        h = h.wrapping_mul(Self::INVERSE_MULTIPLIERS[0]);
         h ^= h >> 33;
         h
     }
@@ -32,35 +155,414 @@ impl StatelessU64Hasher for MurmurHasher {
     fn hash(value: u64) -> u64 {
         Self::hash_u64(value)
     }
+
+    #[inline(always)]
+    fn hash_batch(values: &[u64], hashes: &mut [u64]) {
+        let vector = hash_batch::xorshift_multiply(0, [33; 3], Self::MULTIPLIERS);
+        hash_batch::map(values, hashes, vector, Self::hash_u64);
+    }
+
+    #[inline(always)]
+    fn hash_batch_in_place(values: &mut [u64]) {
+        let vector = hash_batch::xorshift_multiply(0, [33; 3], Self::MULTIPLIERS);
+        hash_batch::map_in_place(values, vector, Self::hash_u64);
+    }
+}
+
+impl InvertibleU64Hasher for MurmurHasher {
+    #[inline(always)]
+    fn unhash(&self, hash: u64) -> u64 {
+        Self::unhash_u64(hash)
+    }
 }
 
+#[derive(Clone, Copy, Default)]
 pub struct MulSwapMulHasher;
 
+impl MulSwapMulHasher {
+    const MULTIPLIERS: [u64; 2] = [0x9e3779b97f4a7c15, 0xc2b2ae3d27d4eb4f];
+    const INVERSE_MULTIPLIERS: [u64; 2] = [inverse_mul(Self::MULTIPLIERS[0]), inverse_mul(Self::MULTIPLIERS[1])];
+}
+
 impl StatelessU64Hasher for MulSwapMulHasher {
     #[inline(always)]
     fn hash(value: u64) -> u64 {
         // Cheap bijective hasher: multiply-byteswap-multiply
         let mut h = value;
-        h = h.wrapping_mul(0x9e3779b97f4a7c15); // First odd constant
+        h = h.wrapping_mul(Self::MULTIPLIERS[0]); // First odd constant
         h = h.swap_bytes(); // Byte swap
-        h = h.wrapping_mul(0xc2b2ae3d27d4eb4f); // Second odd constant
+        h = h.wrapping_mul(Self::MULTIPLIERS[1]); // Second odd constant
+        h
+    }
+
+    #[inline(always)]
+    fn hash_batch(values: &[u64], hashes: &mut [u64]) {
+        let vector = hash_batch::mul_swap_mul(Self::MULTIPLIERS);
+        hash_batch::map(values, hashes, vector, <Self as StatelessU64Hasher>::hash);
+    }
+
+    #[inline(always)]
+    fn hash_batch_in_place(values: &mut [u64]) {
+        let vector = hash_batch::mul_swap_mul(Self::MULTIPLIERS);
+        hash_batch::map_in_place(values, vector, <Self as StatelessU64Hasher>::hash);
+    }
+}
+
+impl InvertibleU64Hasher for MulSwapMulHasher {
+    #[inline(always)]
+    fn unhash(&self, hash: u64) -> u64 {
+        let mut h = hash;
+        h = h.wrapping_mul(Self::INVERSE_MULTIPLIERS[1]);
+        h = h.swap_bytes();
+        h = h.wrapping_mul(Self::INVERSE_MULTIPLIERS[0]);
+        h
+    }
+}
+
+/// wyhash's `wyhash64`, on the key and zero: two rounds of a 64x64->128-bit multiply, folding the halves together.
+///
+/// Folding the halves loses information, so this is not a bijection: distinct keys can share a hash. The hashed sorts
+/// count distinct hashes, so with this hasher their counts are approximate, undercounting by any collisions.
+#[derive(Clone, Copy, Default)]
+pub struct WyHasher;
+
+impl WyHasher {
+    const SECRET: [u64; 2] = [0x2d358dccaa6c78a5, 0x8bb84b93962eacc9];
+
+    /// wyhash's `_wymum`: returns the low and high halves of the product.
+    #[inline(always)]
+    fn mum(a: u64, b: u64) -> (u64, u64) {
+        let product = a as u128 * b as u128;
+        (product as u64, (product >> 64) as u64)
+    }
+}
+
+impl StatelessU64Hasher for WyHasher {
+    #[inline(always)]
+    fn hash(value: u64) -> u64 {
+        let (a, b) = Self::mum(value ^ Self::SECRET[0], Self::SECRET[1]);
+        let (lo, hi) = Self::mum(a ^ Self::SECRET[0], b ^ Self::SECRET[1]);
+        lo ^ hi
+    }
+}
+
+/// Pelle Evensen's moremur, a Murmur-style finalizer with better-tuned shifts and multipliers.
+#[derive(Clone, Copy, Default)]
+pub struct MoremurHasher;
+
+impl MoremurHasher {
+    const SHIFTS: [u32; 3] = [27, 33, 27];
+    const MULTIPLIERS: [u64; 2] = [0x3c79ac492ba7b653, 0x1c69b3f74ac4ae35];
+}
+
+impl StatelessU64Hasher for MoremurHasher {
+    #[inline(always)]
+    fn hash(value: u64) -> u64 {
+        let mut h = value;
+        h ^= h >> Self::SHIFTS[0];
+        h = h.wrapping_mul(Self::MULTIPLIERS[0]);
+        h ^= h >> Self::SHIFTS[1];
+        h = h.wrapping_mul(Self::MULTIPLIERS[1]);
+        h ^= h >> Self::SHIFTS[2];
         h
     }
+
+    #[inline(always)]
+    fn hash_batch(values: &[u64], hashes: &mut [u64]) {
+        let vector = hash_batch::xorshift_multiply(0, Self::SHIFTS, Self::MULTIPLIERS);
+        hash_batch::map(values, hashes, vector, <Self as StatelessU64Hasher>::hash);
+    }
+
+    #[inline(always)]
+    fn hash_batch_in_place(values: &mut [u64]) {
+        let vector = hash_batch::xorshift_multiply(0, Self::SHIFTS, Self::MULTIPLIERS);
+        hash_batch::map_in_place(values, vector, <Self as StatelessU64Hasher>::hash);
+    }
 }
 
-pub struct U64Hasher<Hasher: StatelessU64Hasher> {
-    result: u64, 
-    function: std::marker::PhantomData<Hasher>,
+/// Pelle Evensen's rrmxmx: a rotate-xor step, then the same two rounds of multiply and xorshift as moremur, with one
+/// multiplier and a shift of 28 in both.
+///
+/// xxh3's finalizer for 4-8 byte inputs is a variant of this, not this exact function: its first xorshift shifts by 35
+/// plus the input length.
+#[derive(Clone, Copy, Default)]
+pub struct RrmxmxHasher;
+
+impl RrmxmxHasher {
+    const MULTIPLIER: u64 = 0x9fb21c651e98df25;
 }
 
-impl<Hasher: StatelessU64Hasher> std::hash::Hasher for U64Hasher<Hasher> {
-    fn write(&mut self, _bytes: &[u8]) {
-        unreachable!("Expected u64, got bytes");
+impl StatelessU64Hasher for RrmxmxHasher {
+    #[inline(always)]
+    fn hash(value: u64) -> u64 {
+        let mut h = value;
+        h ^= h.rotate_right(49) ^ h.rotate_right(24);
+        h = h.wrapping_mul(Self::MULTIPLIER);
+        h ^= h >> 28;
+        h = h.wrapping_mul(Self::MULTIPLIER);
+        h ^= h >> 28;
+        h
+    }
+
+    #[inline(always)]
+    fn hash_batch(values: &[u64], hashes: &mut [u64]) {
+        let vector = hash_batch::rrmxmx(Self::MULTIPLIER);
+        hash_batch::map(values, hashes, vector, <Self as StatelessU64Hasher>::hash);
+    }
+
+    #[inline(always)]
+    fn hash_batch_in_place(values: &mut [u64]) {
+        let vector = hash_batch::rrmxmx(Self::MULTIPLIER);
+        hash_batch::map_in_place(values, vector, <Self as StatelessU64Hasher>::hash);
+    }
+}
+
+/// Hashes with the SSE 4.2 CRC32C instruction: the key's CRC in both halves, xored with the key's halves swapped.
+///
+/// CRC is linear over GF(2), so this mixes much worse than the multiply-based hashers. But the whole map is a linear
+/// bijection, so distinct keys never collide, which the hashed sorts rely on. Needs SSE 4.2 at run time: `hash` panics
+/// without it, so check `is_supported` first.
+#[derive(Clone, Copy, Default)]
+pub struct Crc32cHasher;
+
+impl Crc32cHasher {
+    pub fn is_supported() -> bool {
+        #[cfg(target_arch = "x86_64")]
+        return std::arch::is_x86_feature_detected!("sse4.2");
+        #[cfg(not(target_arch = "x86_64"))]
+        return false;
+    }
+}
+
+impl StatelessU64Hasher for Crc32cHasher {
+    /// Panics if the CPU lacks SSE 4.2.
+    #[inline(always)]
+    fn hash(value: u64) -> u64 {
+        // The check is compiled out when the build enables SSE 4.2, e.g. with `-C target-cpu=native`.
+        #[cfg(target_arch = "x86_64")]
+        if std::arch::is_x86_feature_detected!("sse4.2") {
+            return unsafe { x86::crc32c_hash(value) };
+        }
+        panic!("Crc32cHasher needs SSE 4.2");
+    }
+}
+
+/// Hashes with two rounds of AES encryption (AES-NI), which is enough for every output byte to depend on every key
+/// byte, then folds the 128-bit state to 64 bits. Needs AES-NI at run time: `hash` panics without it, so check
+/// `is_supported` first.
+///
+/// The fold loses information, so like `WyHasher` this is not a bijection, and the hashed sorts' counts with it are
+/// approximate.
+#[derive(Clone, Copy, Default)]
+pub struct AesHasher;
+
+impl AesHasher {
+    pub fn is_supported() -> bool {
+        #[cfg(target_arch = "x86_64")]
+        return std::arch::is_x86_feature_detected!("aes");
+        #[cfg(not(target_arch = "x86_64"))]
+        return false;
+    }
+}
+
+impl StatelessU64Hasher for AesHasher {
+    /// Panics if the CPU lacks AES-NI.
+    #[inline(always)]
+    fn hash(value: u64) -> u64 {
+        #[cfg(target_arch = "x86_64")]
+        if std::arch::is_x86_feature_detected!("aes") {
+            return unsafe { x86::aes_hash(value) };
+        }
+        panic!("AesHasher needs AES-NI");
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    #[inline]
+    #[target_feature(enable = "sse4.2")]
+    pub fn crc32c_hash(value: u64) -> u64 {
+        let crc = _mm_crc32_u64(0, value);
+        ((crc << 32) | crc) ^ value.rotate_left(32)
+    }
+
+    #[inline]
+    #[target_feature(enable = "aes")]
+    pub fn aes_hash(value: u64) -> u64 {
+        // Round keys: the fractional part of pi.
+        let key0 = _mm_set_epi64x(0x243f6a8885a308d3u64 as i64, 0x13198a2e03707344);
+        let key1 = _mm_set_epi64x(0xa4093822299f31d0u64 as i64, 0x082efa98ec4e6c89);
+        let state = _mm_aesenc_si128(_mm_aesenc_si128(_mm_cvtsi64_si128(value as i64), key0), key1);
+        (_mm_cvtsi128_si64(state) ^ _mm_cvtsi128_si64(_mm_unpackhi_epi64(state, state))) as u64
+    }
+}
+
+/// `MurmurHasher` with a seed, xored into the key before the finalizer.
+#[derive(Clone, Copy)]
+pub struct SeededMurmurHasher {
+    seed: u64,
+}
+
+impl SeededMurmurHasher {
+    pub fn with_seed(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// A hasher with a random seed, different on every run.
+    pub fn random() -> Self {
+        Self::with_seed(random_seed())
+    }
+}
+
+impl SeededU64Hasher for SeededMurmurHasher {
+    #[inline(always)]
+    fn hash(&self, value: u64) -> u64 {
+        MurmurHasher::hash_u64(value ^ self.seed)
+    }
+
+    #[inline(always)]
+    fn hash_batch(&self, values: &[u64], hashes: &mut [u64]) {
+        let vector = hash_batch::xorshift_multiply(self.seed, [33; 3], MurmurHasher::MULTIPLIERS);
+        hash_batch::map(values, hashes, vector, |value| self.hash(value));
+    }
+
+    #[inline(always)]
+    fn hash_batch_in_place(&self, values: &mut [u64]) {
+        let vector = hash_batch::xorshift_multiply(self.seed, [33; 3], MurmurHasher::MULTIPLIERS);
+        hash_batch::map_in_place(values, vector, |value| self.hash(value));
+    }
+}
+
+impl InvertibleU64Hasher for SeededMurmurHasher {
+    #[inline(always)]
+    fn unhash(&self, hash: u64) -> u64 {
+        MurmurHasher::unhash_u64(hash) ^ self.seed
+    }
+}
+
+/// `MulSwapMulHasher` with both multipliers drawn from a seed rather than fixed.
+///
+/// Xoring a seed into the key, as `SeededMurmurHasher` does, wouldn't help here: keys that differ only in their high
+/// bits still collide after a multiply, whatever the seed. Random odd multipliers make that depend on the seed. It
+/// costs the same as `MulSwapMulHasher`, and stays bijective.
+#[derive(Clone, Copy)]
+pub struct SeededMulSwapMulHasher {
+    multipliers: [u64; 2],
+    inverse_multipliers: [u64; 2],
+}
+
+impl SeededMulSwapMulHasher {
+    pub fn with_seed(seed: u64) -> Self {
+        // Spread the seed over both multipliers with two splitmix64 steps, then make them odd.
+        let mut state = seed;
+        let multipliers = std::array::from_fn(|_| {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            (z ^ (z >> 31)) | 1
+        });
+        Self {
+            multipliers,
+            inverse_multipliers: multipliers.map(inverse_mul),
+        }
+    }
+
+    /// A hasher with a random seed, different on every run.
+    pub fn random() -> Self {
+        Self::with_seed(random_seed())
+    }
+}
+
+impl SeededU64Hasher for SeededMulSwapMulHasher {
+    #[inline(always)]
+    fn hash(&self, value: u64) -> u64 {
+        let mut h = value;
+        h = h.wrapping_mul(self.multipliers[0]);
+        h = h.swap_bytes();
+        h = h.wrapping_mul(self.multipliers[1]);
+        h
+    }
+
+    #[inline(always)]
+    fn hash_batch(&self, values: &[u64], hashes: &mut [u64]) {
+        hash_batch::map(values, hashes, hash_batch::mul_swap_mul(self.multipliers), |value| self.hash(value));
+    }
+
+    #[inline(always)]
+    fn hash_batch_in_place(&self, values: &mut [u64]) {
+        hash_batch::map_in_place(values, hash_batch::mul_swap_mul(self.multipliers), |value| self.hash(value));
+    }
+}
+
+impl InvertibleU64Hasher for SeededMulSwapMulHasher {
+    #[inline(always)]
+    fn unhash(&self, hash: u64) -> u64 {
+        let mut h = hash;
+        h = h.wrapping_mul(self.inverse_multipliers[1]);
+        h = h.swap_bytes();
+        h = h.wrapping_mul(self.inverse_multipliers[0]);
+        h
+    }
+}
+
+/// Adapts a u64 hasher to `std::hash::Hasher`.
+///
+/// Each word written is xored into the state, which is then hashed, so a single `u64` key hashes to exactly
+/// `hash(key)`. Narrower integers are widened to a word, `u128`s are written as two, and byte strings are folded in a
+/// word at a time, zero-padded, followed by their length.
+pub struct U64Hasher<Hasher: SeededU64Hasher> {
+    result: u64,
+    function: Hasher,
+}
+
+impl<Hasher: SeededU64Hasher> std::hash::Hasher for U64Hasher<Hasher> {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        let (words, tail) = bytes.as_chunks::<8>();
+        for &word in words {
+            self.write_u64(u64::from_le_bytes(word));
+        }
+        if !tail.is_empty() {
+            let mut word = [0u8; 8];
+            word[..tail.len()].copy_from_slice(tail);
+            self.write_u64(u64::from_le_bytes(word));
+        }
+        // The padding makes trailing zero bytes look like no bytes, so the length tells them apart.
+        self.write_u64(bytes.len() as u64);
+    }
+
+    #[inline(always)]
+    fn write_u8(&mut self, value: u8) {
+        self.write_u64(value as u64);
+    }
+
+    #[inline(always)]
+    fn write_u16(&mut self, value: u16) {
+        self.write_u64(value as u64);
+    }
+
+    #[inline(always)]
+    fn write_u32(&mut self, value: u32) {
+        self.write_u64(value as u64);
     }
 
     #[inline(always)]
     fn write_u64(&mut self, value: u64) {
-        self.result = Hasher::hash(value);
+        self.result = self.function.hash(self.result ^ value);
+    }
+
+    #[inline(always)]
+    fn write_u128(&mut self, value: u128) {
+        self.write_u64(value as u64);
+        self.write_u64((value >> 64) as u64);
+    }
+
+    #[inline(always)]
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
     }
 
     #[inline(always)]
@@ -69,11 +571,156 @@ impl<Hasher: StatelessU64Hasher> std::hash::Hasher for U64Hasher<Hasher> {
     }
 }
 
-impl<Hasher: StatelessU64Hasher> Default for U64Hasher<Hasher> {
+impl<Hasher: SeededU64Hasher + Default> Default for U64Hasher<Hasher> {
     fn default() -> Self {
         Self {
             result: 0,
-            function: std::marker::PhantomData,
+            function: Hasher::default(),
+        }
+    }
+}
+
+/// A `BuildHasher` that hands its hasher to every `U64Hasher` it builds, so seeded hashers can back std hash tables.
+///
+/// `BuildHasherDefault` only works for hashers without a seed: seeded ones deliberately have no `Default`, since each
+/// table needs one seed throughout.
+#[derive(Clone, Copy)]
+pub struct BuildU64Hasher<Hasher: SeededU64Hasher>(pub Hasher);
+
+impl<Hasher: SeededU64Hasher> std::hash::BuildHasher for BuildU64Hasher<Hasher> {
+    type Hasher = U64Hasher<Hasher>;
+
+    #[inline(always)]
+    fn build_hasher(&self) -> U64Hasher<Hasher> {
+        U64Hasher {
+            result: 0,
+            function: self.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEEDS: [u64; 4] = [0, 1, 0x0123_4567_89ab_cdef, u64::MAX];
+
+    /// 0, 1, u64::MAX, every single-bit word and every word with a single bit clear, then random words.
+    fn words() -> Vec<u64> {
+        let mut words = vec![0, 1, u64::MAX];
+        words.extend((0..64).map(|i| 1u64 << i));
+        words.extend((0..64).map(|i| !(1u64 << i)));
+        let mut rng = fastrand::Rng::with_seed(1);
+        words.extend((0..100_000).map(|_| rng.u64(..)));
+        words
+    }
+
+    fn check_round_trip<H: InvertibleU64Hasher>(hasher: H, name: &str) {
+        for x in words() {
+            assert_eq!(hasher.unhash(hasher.hash(x)), x, "{name}: unhash(hash({x:#x}))");
+            assert_eq!(hasher.hash(hasher.unhash(x)), x, "{name}: hash(unhash({x:#x}))");
+        }
+    }
+
+    #[test]
+    fn invertible_hashers_round_trip() {
+        check_round_trip(NoopHasher, "NoopHasher");
+        check_round_trip(MurmurHasher, "MurmurHasher");
+        check_round_trip(MulSwapMulHasher, "MulSwapMulHasher");
+        for seed in SEEDS {
+            check_round_trip(SeededMurmurHasher::with_seed(seed), &format!("SeededMurmurHasher({seed:#x})"));
+            check_round_trip(SeededMulSwapMulHasher::with_seed(seed), &format!("SeededMulSwapMulHasher({seed:#x})"));
+        }
+    }
+
+    /// Checks `hash_batch` and `hash_batch_in_place` against `hash`, at lengths that leave every possible number of
+    /// words over after the vectors.
+    fn check_batch<H: SeededU64Hasher>(hasher: H, name: &str) {
+        let mut rng = fastrand::Rng::with_seed(3);
+        for len in (0..=40).chain([1000, 1001, 1007]) {
+            let values: Vec<u64> = (0..len).map(|_| rng.u64(..)).collect();
+            let expected: Vec<u64> = values.iter().map(|&value| hasher.hash(value)).collect();
+            let mut hashes = vec![0; len];
+            hasher.hash_batch(&values, &mut hashes);
+            assert_eq!(hashes, expected, "{name}: hash_batch, len {len}");
+            let mut in_place = values.clone();
+            hasher.hash_batch_in_place(&mut in_place);
+            assert_eq!(in_place, expected, "{name}: hash_batch_in_place, len {len}");
+        }
+    }
+
+    #[test]
+    fn batches_match_scalar() {
+        check_batch(NoopHasher, "NoopHasher");
+        check_batch(MurmurHasher, "MurmurHasher");
+        check_batch(MulSwapMulHasher, "MulSwapMulHasher");
+        check_batch(WyHasher, "WyHasher");
+        check_batch(MoremurHasher, "MoremurHasher");
+        check_batch(RrmxmxHasher, "RrmxmxHasher");
+        if Crc32cHasher::is_supported() {
+            check_batch(Crc32cHasher, "Crc32cHasher");
+        }
+        if AesHasher::is_supported() {
+            check_batch(AesHasher, "AesHasher");
+        }
+        for seed in SEEDS {
+            check_batch(SeededMurmurHasher::with_seed(seed), &format!("SeededMurmurHasher({seed:#x})"));
+            check_batch(SeededMulSwapMulHasher::with_seed(seed), &format!("SeededMulSwapMulHasher({seed:#x})"));
+        }
+    }
+
+    /// Checks that `with_seed` gives the same hashes for the same seed and different ones for different seeds.
+    fn check_seed_matters<H: SeededU64Hasher>(with_seed: impl Fn(u64) -> H, name: &str) {
+        let words = words();
+        let hashes = |seed| words.iter().map(|&x| with_seed(seed).hash(x)).collect::<Vec<_>>();
+        let by_seed = SEEDS.map(hashes);
+        for (i, seed) in SEEDS.into_iter().enumerate() {
+            assert_eq!(hashes(seed), by_seed[i], "{name}: seed {seed:#x} hashed differently twice");
+            for (j, other) in SEEDS.into_iter().enumerate().skip(i + 1) {
+                let same = by_seed[i].iter().zip(&by_seed[j]).filter(|(a, b)| a == b).count();
+                // SeededMulSwapMulHasher hashes 0 to 0 under every seed. Any other word agreeing would be a 2^-64 chance.
+                assert!(same <= 1, "{name}: seeds {seed:#x} and {other:#x} agree on {same} words");
+            }
+        }
+    }
+
+    #[test]
+    fn seeds_change_hashes() {
+        check_seed_matters(SeededMurmurHasher::with_seed, "SeededMurmurHasher");
+        check_seed_matters(SeededMulSwapMulHasher::with_seed, "SeededMulSwapMulHasher");
+    }
+
+    #[test]
+    fn u64_hasher_bytes_depend_on_length() {
+        use std::hash::Hasher;
+        let hash_bytes = |bytes: &[u8]| {
+            let mut hasher = U64Hasher::<MurmurHasher>::default();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+        let mut hashes: Vec<u64> = (0..=17).map(|len| hash_bytes(&vec![0; len])).collect();
+        hashes.extend([hash_bytes(b"a"), hash_bytes(b"a\0"), hash_bytes(b"abcdefgh"), hash_bytes(b"abcdefgh\0")]);
+        let distinct: std::collections::HashSet<u64> = hashes.iter().copied().collect();
+        assert_eq!(distinct.len(), hashes.len(), "byte strings differing only in trailing zeros collide");
+    }
+
+    #[test]
+    fn inverse_multipliers() {
+        // The hashers' own multipliers and precomputed inverses.
+        let mut pairs = vec![];
+        pairs.extend(MurmurHasher::MULTIPLIERS.into_iter().zip(MurmurHasher::INVERSE_MULTIPLIERS));
+        pairs.extend(MulSwapMulHasher::MULTIPLIERS.into_iter().zip(MulSwapMulHasher::INVERSE_MULTIPLIERS));
+        for seed in SEEDS {
+            let hasher = SeededMulSwapMulHasher::with_seed(seed);
+            pairs.extend(hasher.multipliers.into_iter().zip(hasher.inverse_multipliers));
+        }
+        // And `inverse_mul` on its own, for edge and random odd multipliers.
+        let mut rng = fastrand::Rng::with_seed(2);
+        let others = [1, 3, u64::MAX].into_iter().chain((0..1000).map(|_| rng.u64(..) | 1));
+        pairs.extend(others.map(|m| (m, inverse_mul(m))));
+        for (m, inverse) in pairs {
+            assert_eq!(inverse, inverse_mul(m), "multiplier {m:#x}");
+            assert_eq!(inverse.wrapping_mul(m), 1, "multiplier {m:#x}");
         }
     }
 }
@@ -0,0 +1,53 @@
+//! Counting the unique values in a large array of u64s, with hash sets, sorts, and hashed sorts.
+//!
+//! The main entry points are re-exported here:
+//!
+//! - `count_unique`, which picks one of the algorithms below from the input's size, its estimated duplication and
+//!   the cache size.
+//! - `U64HashSet`, a dense_hash_set for u64 keys, and `U64CountMap`, a dense_hash_map counting them.
+//! - `dlsd_sort` and `dlsd_sort_and_count`, which radix sort the keys' hashes, the latter counting the unique ones
+//!   as it goes.
+//! - `wide_merge_sort`, a comparison sort merging 256 runs at a time.
+//!
+//! The hashers they take are in `hashers`, scored for quality by `hash_quality`, and `counters` wraps every algorithm as a `count_unique_*` function.
+//! `dedup_unique` returns the distinct words themselves, with hash sets, sorts and hashed sorts, and
+//! `count_occurrences` counts each of them, with `U64CountMap` or hashed sorts. `top_k` keeps the most frequent ones,
+//! exactly from those counts or approximately with `SpaceSaving`.
+//! `distinct_counter` puts the counting algorithms behind one trait, with a registry of every algorithm and hasher.
+//! `workloads` generates the keys the benchmark runs on.
+//! Each module also has `_with_scratch` versions of its sorts, which use caller-owned buffers rather than allocating.
+//! The buffers are `MaybeUninit`, so they never need zeroing.
+
+#[cfg(test)]
+mod alloc_count;
+pub mod auto;
+pub mod count_occurrences;
+pub mod counters;
+pub mod dedup_unique;
+mod dense_table;
+pub mod distinct_counter;
+pub mod dlsd;
+pub mod dlsd_and_count;
+pub mod external_sort;
+mod hash_batch;
+pub mod hash_quality;
+pub mod hashers;
+pub mod msd;
+pub mod phase_timing;
+mod small_sort;
+#[cfg(test)]
+mod test_inputs;
+pub mod top_k;
+pub mod u64_count_map;
+pub mod u64_hash_set;
+mod uninit;
+pub mod wide_merge_sort;
+pub mod wide_merge_sort_by_key;
+pub mod workloads;
+
+pub use auto::count_unique;
+pub use dlsd::dlsd_sort;
+pub use dlsd_and_count::dlsd_sort_and_count;
+pub use u64_count_map::U64CountMap;
+pub use u64_hash_set::U64HashSet;
+pub use wide_merge_sort::wide_merge_sort;
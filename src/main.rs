@@ -1,26 +1,36 @@
-#![allow(internal_features)]
-#![feature(core_intrinsics)]
-mod dlsd;
-mod dlsd_and_count;
-mod hashers;
-mod u64_hash_set;
-mod wide_merge_sort;
+mod hash_quality_report;
+mod peak_alloc;
 
 use dashmap::DashMap;
-use fastrand;
-use foldhash::fast::RandomState as FoldRandomState;
-use hashers::{MulSwapMulHasher, MurmurHasher, NoopHasher, StatelessU64Hasher, U64Hasher};
+use hashed_sorting_benchmark::auto::{Decision, cache_sizes, count_unique_with_decision};
+use hashed_sorting_benchmark::count_occurrences::*;
+use hashed_sorting_benchmark::count_unique;
+use hashed_sorting_benchmark::counters::*;
+use hashed_sorting_benchmark::dedup_unique::*;
+use hashed_sorting_benchmark::distinct_counter::{
+    Counter, CountingMethod, DistinctCounter, HasherKind, registry, swiss_table_bytes,
+};
+use hashed_sorting_benchmark::hashers::{
+    InvertibleU64Hasher, MulSwapMulHasher, MurmurHasher, SeededMulSwapMulHasher, SeededMurmurHasher,
+};
+use hashed_sorting_benchmark::phase_timing::{self, PhaseKey};
+use hashed_sorting_benchmark::top_k::*;
+use hashed_sorting_benchmark::wide_merge_sort_by_key::wide_merge_sort_by_key;
+use hashed_sorting_benchmark::{U64CountMap, U64HashSet};
+use hashed_sorting_benchmark::with_build_hasher;
+use hashed_sorting_benchmark::workloads::{MaskStyle, random_keys, zipf_keys};
 use rayon::prelude::*;
 use scc::HashSet as SccHashSet;
+use std::cell::OnceCell;
 use std::collections::HashSet;
-use std::hash::{BuildHasher, BuildHasherDefault, RandomState};
+use std::hash::BuildHasher;
 use std::time::{Duration, Instant};
-use u64_hash_set::U64HashSet;
 use voracious_radix_sort::RadixSort;
-use wide_merge_sort::wide_merge_sort;
 
-use crate::dlsd::dlsd_sort;
-use crate::dlsd_and_count::dlsd_sort_and_count;
+use crate::peak_alloc::PeakAlloc;
+
+#[global_allocator]
+static PEAK_ALLOC: PeakAlloc = PeakAlloc::new();
 
 
 // Configuration choices:
@@ -28,24 +38,34 @@ const MASK_STYLE: MaskStyle = MaskStyle::SpreadOut2x;
 const LG_ACCESSES_PER_ELEMENT: usize = 0;
 const BENCHMARK_FILTERS: &[&str] = &["HashSet (SwissTable + MulSwapMul)", "Sorting (quick sort)", "HashSet (dense_table + MulSwapMul)", "Hashed sorting (fused dlsd_and_count + MulSwapMul)"];
 const SIZES: &[usize] = &[10, 15, 20, 25, 28];
-const PREFETCH_DISTANCE: usize = 64;
-
+/// Whether to also benchmark each algorithm with its scratch space reused across calls, i.e. without allocation.
+const BENCHMARK_REUSED_SCRATCH: bool = true;
+/// Whether to also benchmark the `dedup_unique` functions at each size.
+const DEDUP_BENCHMARKS: bool = false;
+/// Seed for the seeded hashers. `None` picks a random seed on every run, as a hash table facing untrusted keys would.
+const HASH_SEED: Option<u64> = None;
+/// Whether to print the hash quality report (see `hash_quality_report`) before the benchmarks.
+const HASH_QUALITY_REPORT: bool = false;
+/// Whether to run the occurrence-counting sweep (see `occurrences_sweep`) before the main benchmarks.
+const OCCURRENCES_SWEEP: bool = false;
+/// Input size of the occurrence-counting sweep, as log2 of its number of words.
+const OCCURRENCES_LG_SIZE: usize = 20;
+/// Duplicate ratios of the occurrence-counting sweep, as log2 of the average occurrences of each distinct word.
+const OCCURRENCES_LG_ACCESSES_PER_ELEMENT: &[usize] = &[0, 3, 6, 12];
+/// Whether to run the top-k sweep (see `top_k_sweep`) before the main benchmarks.
+const TOP_K_SWEEP: bool = false;
+/// Input size of the top-k sweep, as log2 of its number of words.
+const TOP_K_LG_SIZE: usize = 22;
+/// Number of distinct words the top-k sweep draws from, as log2.
+const TOP_K_LG_DOMAIN_SIZE: usize = 20;
+/// Number of most frequent words the top-k sweep looks for.
+const TOP_K: usize = 16;
+/// Zipf exponents of the top-k sweep: the i-th most frequent word occurs in proportion to 1 / i^exponent.
+const TOP_K_ZIPF_EXPONENTS: &[f64] = &[0.7, 1.0, 1.3];
+/// Numbers of counters `SpaceSaving` gets in the top-k sweep.
+const TOP_K_SPACE_SAVING_COUNTERS: &[usize] = &[256, 4096];
 
 
-#[derive(Debug)]
-#[allow(dead_code)]
-enum MaskStyle {
-    /// All the entropy is in the low bits. Friendly to most algorithms, even with Noop hashing.
-    LowBits,
-    /// All the entropy is in the high bits. Unfriendly to most hashing algorithms, which need entropy in the low bits.
-    /// Friendly enough for radix sort algorithms which are adaptive to where the entropy is.
-    HighBits,
-    /// The entropy is spread out over pairs of bits: each even bit is equal to the next odd bit. This tends to be
-    /// unfriendly to NoOp hashing both for hashing and radix sort algorithms.
-    SpreadOut2x,
-    /// All bits are used.
-    AllBits,
-}
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -58,36 +78,23 @@ enum AccessFrequency {
 
 
 
-fn count_unique_by_hash<Hasher: BuildHasher>(
-    data: &[u64],
-    hasher: Hasher,
-    domain_size: usize,
-) -> usize {
-    let mut hasher = HashSet::with_capacity_and_hasher(domain_size, hasher);
-    for d in data {
-        hasher.insert(*d);
-    }
-    hasher.len()
-}
-
-fn count_unique_by_u64_hash<H: StatelessU64Hasher>(data: &[u64], domain_size: usize) -> usize {
-    let mut set = U64HashSet::<H>::with_capacity(domain_size);
-    for (&d, &prefetch_d) in data.iter().zip(data.iter().skip(PREFETCH_DISTANCE)) {
-        set.prefetch(prefetch_d);
-        set.insert(d);
+/// Like `count_unique_by_hash`, but with each key split into a `(u32, u32)` pair, as for a composite key.
+fn count_unique_by_pair_hash<Hasher: BuildHasher>(data: &[u64], set: &mut HashSet<(u32, u32), Hasher>) -> usize {
+    set.clear();
+    for &d in data {
+        set.insert(((d >> 32) as u32, d as u32));
     }
     set.len()
 }
 
 fn count_unique_by_parallel_hash<Hasher: BuildHasher + Clone + Send + Sync>(
     data: &[u64],
-    hasher: Hasher,
-    domain_size: usize,
+    dashmap: &mut DashMap<u64, (), Hasher>,
 ) -> usize
 where
     Hasher::Hasher: Send,
 {
-    let dashmap = DashMap::with_capacity_and_hasher(domain_size, hasher);
+    dashmap.clear();
     data.par_iter().for_each(|&d| {
         dashmap.insert(d, ());
     });
@@ -96,114 +103,496 @@ where
 
 fn count_unique_by_scc_parallel_hash<Hasher: BuildHasher + Sync>(
     data: &[u64],
-    hasher: Hasher,
-    domain_size: usize,
+    scc_set: &mut SccHashSet<u64, Hasher>,
 ) -> usize {
-    let scc_set: SccHashSet<u64, Hasher> =
-        SccHashSet::with_capacity_and_hasher(domain_size, hasher);
+    scc_set.clear();
     data.par_iter().for_each(|&d| {
         let _result = scc_set.insert(d);
     });
     scc_set.len()
 }
 
-fn count_unique_by_sort<F>(data: &[u64], sort_fn: F) -> usize
-where
-    F: FnOnce(&mut Vec<u64>),
-{
-    let mut sorted_data = data.to_vec();
-    sort_fn(&mut sorted_data);
-    count_unique_in_sorted(&sorted_data)
+fn count_unique_by_record_sort(data: &[u64], sort_fn: impl FnOnce(&mut [(u64, u64)])) -> usize {
+    // Records carrying their input position, as for a join or group-by.
+    let mut records: Vec<(u64, u64)> = data.iter().enumerate().map(|(i, &d)| (d, i as u64)).collect();
+    sort_fn(&mut records);
+    count_unique_in_sorted_by_key(&records, |&(key, _)| key)
 }
 
-fn count_unique_by_hashed_sort<H: StatelessU64Hasher>(data: &[u64]) -> usize {
-    let mut hashed_data = data.iter().map(|&d| H::hash(d)).collect::<Vec<_>>();
-    hashed_data.voracious_sort();
-    let count = count_unique_in_sorted(&hashed_data);
-    std::hint::black_box(count)
+/// Counters for what only the benchmark compares against: composite keys, records, and other crates' concurrent hash
+/// tables.
+fn benchmark_only_counters(capacity: usize, seed: Option<u64>) -> Vec<Box<dyn DistinctCounter>> {
+    let mut counters: Vec<Box<dyn DistinctCounter>> = Vec::new();
+    for kind in HasherKind::ALL.iter().copied().filter(|kind| kind.is_supported()) {
+        with_build_hasher!(kind, seed, |build_hasher| {
+            let table_bytes = swiss_table_bytes(capacity);
+            let pair_hasher = build_hasher.clone();
+            let counter = Counter::new(
+                CountingMethod::HashSet,
+                "SwissTable, (u32, u32) keys",
+                move || HashSet::with_capacity_and_hasher(capacity, pair_hasher.clone()),
+                count_unique_by_pair_hash,
+            );
+            counters.push(Box::new(counter.hashed_with(kind, HasherKind::ALL).memory(0, table_bytes)));
+            let dashmap_hasher = build_hasher.clone();
+            let counter = Counter::new(
+                CountingMethod::HashSet,
+                "dashmap",
+                move || DashMap::with_capacity_and_hasher(capacity, dashmap_hasher.clone()),
+                count_unique_by_parallel_hash,
+            );
+            counters.push(Box::new(counter.parallel().hashed_with(kind, HasherKind::ALL).memory(0, table_bytes)));
+            let counter = Counter::new(
+                CountingMethod::HashSet,
+                "scc",
+                move || SccHashSet::with_capacity_and_hasher(capacity, build_hasher.clone()),
+                count_unique_by_scc_parallel_hash,
+            );
+            counters.push(Box::new(counter.parallel().hashed_with(kind, HasherKind::ALL).memory(0, table_bytes)));
+        });
+    }
+    let counter = Counter::new(
+        CountingMethod::Sorting,
+        "stable sort by key, (u64, u64) records",
+        || (),
+        |data, _| count_unique_by_record_sort(data, |v| v.sort_by_key(|&(key, _)| key)),
+    );
+    counters.push(Box::new(counter.memory(24, 0)));
+    let counter = Counter::new(
+        CountingMethod::Sorting,
+        "stable wide merge sort by key, (u64, u64) records",
+        || (),
+        |data, _| count_unique_by_record_sort(data, |v| wide_merge_sort_by_key(v, |&(key, _)| key)),
+    );
+    counters.push(Box::new(counter.memory(32, 0)));
+    counters
 }
 
-fn count_unique_by_hashed_dlsd_sort<H: StatelessU64Hasher>(data: &[u64]) -> usize {
-    let sorted_data = dlsd_sort::<H>(data);
-    let count = count_unique_in_sorted(&sorted_data);
-    std::hint::black_box(count)
+/// Name of the `count_unique` benchmark, which is compared against the fastest serial counter that ran.
+const AUTOMATIC: &str = "count_unique (automatic)";
+
+/// Whether `name` matches one of `BENCHMARK_FILTERS`.
+fn is_selected(name: &str) -> bool {
+    BENCHMARK_FILTERS.iter().any(|filter| name.contains(filter))
 }
 
-fn count_unique_by_fused_hashed_dlsd_sort<H: StatelessU64Hasher>(data: &[u64]) -> usize {
-    let count = dlsd_sort_and_count::<H>(data);
-    std::hint::black_box(count)
+/// Runs `f` `repeats` times and prints its average time and peak memory, if `name` is selected. Returns the average
+/// time if it ran.
+fn benchmark<R>(name: &str, repeats: usize, f: impl FnMut() -> R) -> Option<Duration> {
+    benchmark_with_estimate(name, repeats, None, f)
 }
 
-fn count_unique_by_parallel_sort<F>(data: &[u64], sort_fn: F) -> usize
-where
-    F: FnOnce(&mut Vec<u64>),
-{
-    let mut sorted_data = Vec::new();
-    data.par_iter().copied().collect_into_vec(&mut sorted_data);
-    // let mut sorted_data = data.to_vec();
-    sort_fn(&mut sorted_data);
-    count_unique_in_sorted_parallel(&sorted_data)
+/// Like `benchmark`, but also prints `memory_estimate` next to the measured peak memory.
+fn benchmark_with_estimate<R>(
+    name: &str,
+    repeats: usize,
+    memory_estimate: Option<usize>,
+    mut f: impl FnMut() -> R,
+) -> Option<Duration> {
+    if !is_selected(name) {
+        return None;
+    }
+    // Warmup.
+    for _ in 0..repeats {
+        std::hint::black_box(f());
+    }
+    let baseline_memory = PEAK_ALLOC.reset_peak();
+    // Discard phases recorded during warmup.
+    phase_timing::take();
+    let start = Instant::now();
+    for _ in 0..repeats {
+        std::hint::black_box(f());
+    }
+    let duration = start.elapsed();
+    let peak_memory = PEAK_ALLOC.peak() - baseline_memory;
+    let estimate = memory_estimate.map_or(String::new(), |bytes| format!(" (estimate {})", human_size(bytes)));
+    println!(
+        "  {}: {}, peak extra memory: {}{}",
+        name,
+        human_time(repeats, duration),
+        human_size(peak_memory),
+        estimate
+    );
+    print_phase_breakdown(repeats, duration, &phase_timing::take());
+    Some(duration / repeats as u32)
+}
+
+/// Prints per-phase timings recorded with the `phase-timing` feature, as average time per run and share of total.
+fn print_phase_breakdown(repeats: usize, total: Duration, phases: &[(PhaseKey, Duration)]) {
+    if phases.is_empty() {
+        return;
+    }
+    let labels: Vec<String> = phases
+        .iter()
+        .map(|((name, index), _)| match index {
+            Some(index) => format!("{} {}", name, index),
+            None => name.to_string(),
+        })
+        .collect();
+    let width = labels.iter().map(|label| label.len()).max().unwrap_or(0);
+    for (label, (_, duration)) in labels.iter().zip(phases) {
+        println!(
+            "    {:width$}  {:>8}  {:>5.1}%",
+            label,
+            human_time(repeats, *duration),
+            100.0 * duration.as_secs_f64() / total.as_secs_f64(),
+        );
+    }
 }
 
-fn count_unique_by_hashed_parallel_sort<H: StatelessU64Hasher>(
+/// Returns the number of distinct words in `data`, computed on first use and cached in `expected`.
+fn expected_count(data: &[u64], expected: &OnceCell<usize>) -> usize {
+    *expected.get_or_init(|| {
+        let mut sorted_data = data.to_vec();
+        sorted_data.sort_unstable();
+        count_unique_in_sorted(&sorted_data)
+    })
+}
+
+/// Checks `counter`'s count of `data` against `expected` (computed on first use), then benchmarks it. Returns the
+/// average time with allocation, if it ran.
+///
+/// Runs twice: first freeing the counter's scratch space after every call, so allocation and page faults are
+/// included; then, if `BENCHMARK_REUSED_SCRATCH` is set, keeping it across calls, so they are excluded.
+fn benchmark_counter(
+    counter: &mut dyn DistinctCounter,
     data: &[u64],
-    sort_fn: impl FnOnce(&mut Vec<u64>),
-) -> usize {
-    let mut sorted_data = Vec::new();
-    data.par_iter()
-        .map(|&d| H::hash(d))
-        .collect_into_vec(&mut sorted_data);
-    sort_fn(&mut sorted_data);
-    count_unique_in_sorted_parallel(&sorted_data)
+    expected: &OnceCell<usize>,
+    repeats: usize,
+) -> Option<Duration> {
+    let name = counter.name();
+    if !is_selected(&name) {
+        return None;
+    }
+    let count = counter.count(data);
+    counter.free_scratch();
+    let expected = expected_count(data, expected);
+    if count != expected {
+        // Expected with hashers that aren't bijections, if two keys collide; a bug otherwise.
+        println!("  {name}: counted {count} unique values, expected {expected}");
+    }
+    let duration = benchmark_with_estimate(&name, repeats, Some(counter.memory_estimate(data.len())), || {
+        let count = counter.count(data);
+        counter.free_scratch();
+        count
+    });
+    if BENCHMARK_REUSED_SCRATCH {
+        // Allocated during warmup.
+        benchmark(&format!("{} [reused scratch]", name), repeats, || counter.count(data));
+        counter.free_scratch();
+    }
+    duration
 }
 
-fn count_unique_in_sorted(sorted_data: &[u64]) -> usize {
-    if sorted_data.is_empty() {
-        return 0;
+/// Checks and benchmarks `count_unique`, printing its decision for `data` and how it compares to `best_fixed`, the
+/// fastest serial counter's name and time.
+fn benchmark_automatic(data: &[u64], expected: &OnceCell<usize>, repeats: usize, best_fixed: Option<(String, Duration)>) {
+    if !is_selected(AUTOMATIC) {
+        return;
     }
+    let (count, decision) = count_unique_with_decision(data);
+    println!("  {}: {}", AUTOMATIC, describe_decision(&decision));
+    let expected = expected_count(data, expected);
+    if count != expected {
+        println!("  {AUTOMATIC}: counted {count} unique values, expected {expected}");
+    }
+    let Some(duration) = benchmark(AUTOMATIC, repeats, || count_unique(data)) else {
+        return;
+    };
+    if let Some((best_name, best_duration)) = best_fixed {
+        println!(
+            "    {:.2}x the time of the fastest fixed choice, {} ({})",
+            duration.as_secs_f64() / best_duration.as_secs_f64(),
+            best_name,
+            human_time(1, best_duration)
+        );
+    }
+}
 
-    let mut count = 1;
-    let mut prev = sorted_data[0];
+fn describe_decision(decision: &Decision) -> String {
+    format!(
+        "chose {:?}{}, for ~{} distinct of {} words: table {}, cache {}",
+        decision.method,
+        if decision.fell_back { " then fell back to HashedSorting" } else { "" },
+        decision.estimated_distinct,
+        decision.len,
+        human_size(decision.table_bytes),
+        human_size(decision.cache_bytes)
+    )
+}
 
-    for &current in &sorted_data[1..] {
-        count += (current != prev) as usize;
-        prev = current;
+/// Checks that `dedup` returns the distinct words of `data`, in any order, against `expected` (computed on first
+/// use), then benchmarks it.
+fn benchmark_dedup(
+    name: &str,
+    data: &[u64],
+    expected: &OnceCell<Vec<u64>>,
+    repeats: usize,
+    mut dedup: impl FnMut() -> Vec<u64>,
+) {
+    if !is_selected(name) {
+        return;
     }
+    let mut unique = dedup();
+    unique.sort_unstable();
+    let expected = expected.get_or_init(|| dedup_unique_by_sort_and_dedup(data));
+    if unique != *expected {
+        println!("  {name}: returned {} values, expected the {} distinct ones", unique.len(), expected.len());
+    }
+    benchmark(name, repeats, dedup);
+}
 
-    count
+/// Benchmarks the `dedup_unique` functions, with each invertible hasher for the hashed ones.
+fn benchmark_dedups(data: &[u64], capacity: usize, repeats: usize) {
+    let expected = OnceCell::new();
+    benchmark_dedup("Dedup by sorting (sort_unstable + Vec::dedup)", data, &expected, repeats, || {
+        dedup_unique_by_sort_and_dedup(data)
+    });
+    benchmark_dedup("Dedup by sorting (quick sort)", data, &expected, repeats, || {
+        dedup_unique_by_sort(data, |v| v.sort_unstable())
+    });
+    benchmark_dedup("Dedup by sorting (radix sort)", data, &expected, repeats, || {
+        dedup_unique_by_sort(data, |v| v.voracious_sort())
+    });
+    benchmark_hashed_dedups(data, capacity, &expected, repeats, HasherKind::Murmur, MurmurHasher);
+    benchmark_hashed_dedups(data, capacity, &expected, repeats, HasherKind::MulSwapMul, MulSwapMulHasher);
+    let seeded_murmur = HASH_SEED.map_or_else(SeededMurmurHasher::random, SeededMurmurHasher::with_seed);
+    benchmark_hashed_dedups(data, capacity, &expected, repeats, HasherKind::SeededMurmur, seeded_murmur);
+    let seeded_mul_swap_mul = HASH_SEED.map_or_else(SeededMulSwapMulHasher::random, SeededMulSwapMulHasher::with_seed);
+    benchmark_hashed_dedups(data, capacity, &expected, repeats, HasherKind::SeededMulSwapMul, seeded_mul_swap_mul);
 }
 
-fn count_unique_in_sorted_parallel(sorted_data: &[u64]) -> usize {
-    if sorted_data.is_empty() {
-        return 0;
+/// Benchmarks the `dedup_unique` functions that hash, with hasher `h`.
+fn benchmark_hashed_dedups<H: InvertibleU64Hasher>(
+    data: &[u64],
+    capacity: usize,
+    expected: &OnceCell<Vec<u64>>,
+    repeats: usize,
+    kind: HasherKind,
+    h: H,
+) {
+    let hasher = kind.name();
+    benchmark_dedup(&format!("Dedup by HashSet (dense_table + {hasher})"), data, expected, repeats, || {
+        let mut set = U64HashSet::with_capacity_and_hasher(capacity, h);
+        dedup_unique_by_u64_hash(data, &mut set)
+    });
+    benchmark_dedup(&format!("Dedup by hashed sorting (radix + {hasher})"), data, expected, repeats, || {
+        dedup_unique_by_hashed_sort(data, h)
+    });
+    benchmark_dedup(&format!("Dedup by hashed sorting (dlsd + {hasher})"), data, expected, repeats, || {
+        dedup_unique_by_hashed_dlsd_sort(data, h)
+    });
+    benchmark_dedup(&format!("Dedup by hashed sorting (msd + {hasher})"), data, expected, repeats, || {
+        dedup_unique_by_hashed_msd_sort(data, h)
+    });
+}
+
+/// Benchmarks the `dedup_stable_*` functions, with each seeded and unseeded MulSwapMul hasher.
+fn benchmark_stable_dedups(data: &[u64], capacity: usize, repeats: usize) {
+    let expected = OnceCell::new();
+    benchmark_hashed_stable_dedups(data, capacity, &expected, repeats, HasherKind::MulSwapMul, MulSwapMulHasher);
+    let seeded_mul_swap_mul = HASH_SEED.map_or_else(SeededMulSwapMulHasher::random, SeededMulSwapMulHasher::with_seed);
+    benchmark_hashed_stable_dedups(data, capacity, &expected, repeats, HasherKind::SeededMulSwapMul, seeded_mul_swap_mul);
+}
+
+/// Like `benchmark_dedup`, but checks that `dedup` returns the distinct words in the order they first appear.
+fn benchmark_stable_dedup(
+    name: &str,
+    data: &[u64],
+    expected: &OnceCell<Vec<u64>>,
+    repeats: usize,
+    mut dedup: impl FnMut() -> Vec<u64>,
+) {
+    if !is_selected(name) {
+        return;
     }
-    1 + sorted_data
-        .par_windows(2)
-        .map(|w| (w[0] != w[1]) as usize)
-        .sum::<usize>()
+    let unique = dedup();
+    let expected = expected.get_or_init(|| {
+        let mut seen = HashSet::new();
+        data.iter().copied().filter(|&d| seen.insert(d)).collect()
+    });
+    if unique != *expected {
+        println!("  {name}: returned {} values, expected the {} distinct ones in order", unique.len(), expected.len());
+    }
+    benchmark(name, repeats, dedup);
 }
 
-fn benchmark(name: &str, repeats: usize, mut f: impl FnMut()) {
-    let mut run_benchmark = false;
-    for filter in BENCHMARK_FILTERS {
-        if name.contains(filter) {
-            run_benchmark = true;
-            break;
+/// Benchmarks the `dedup_stable_*` functions, with hasher `h`.
+fn benchmark_hashed_stable_dedups<H: InvertibleU64Hasher>(
+    data: &[u64],
+    capacity: usize,
+    expected: &OnceCell<Vec<u64>>,
+    repeats: usize,
+    kind: HasherKind,
+    h: H,
+) {
+    let hasher = kind.name();
+    benchmark_stable_dedup(&format!("Stable dedup by HashSet (dense_table + {hasher})"), data, expected, repeats, || {
+        let mut set = U64HashSet::with_capacity_and_hasher(capacity, h);
+        dedup_stable_by_u64_hash(data, &mut set)
+    });
+    benchmark_stable_dedup(&format!("Stable dedup by hashed sorting (msd + {hasher})"), data, expected, repeats, || {
+        dedup_stable_by_hashed_sort(data, h)
+    });
+}
+
+/// Checks that `count` returns each distinct word of `data` with its count, in any order, against `expected`
+/// (computed on first use), then benchmarks it.
+fn benchmark_occurrences(
+    name: &str,
+    data: &[u64],
+    expected: &OnceCell<Vec<(u64, usize)>>,
+    repeats: usize,
+    mut count: impl FnMut() -> Vec<(u64, usize)>,
+) {
+    if !is_selected(name) {
+        return;
+    }
+    let mut occurrences = count();
+    occurrences.sort_unstable();
+    let expected = expected.get_or_init(|| count_occurrences_by_sort(data));
+    if occurrences != *expected {
+        println!("  {name}: returned {} pairs, expected {} with these counts", occurrences.len(), expected.len());
+    }
+    benchmark(name, repeats, count);
+}
+
+/// Benchmarks the `count_occurrences` functions at `OCCURRENCES_LG_SIZE` words, for every `MaskStyle` and each
+/// duplicate ratio in `OCCURRENCES_LG_ACCESSES_PER_ELEMENT`.
+fn occurrences_sweep() {
+    let mut rng = fastrand::Rng::with_seed(0);
+    let lg_size = OCCURRENCES_LG_SIZE;
+    let repeats = 1usize << 25usize.saturating_sub(lg_size);
+    for mask_style in MaskStyle::ALL {
+        for &lg_accesses_per_element in OCCURRENCES_LG_ACCESSES_PER_ELEMENT {
+            let lg_domain_size = lg_size.saturating_sub(lg_accesses_per_element);
+            let data = random_keys(&mut rng, 1 << lg_size, lg_domain_size, mask_style);
+            println!(
+                "occurrences, mask style: {:?}, average accesses per element: 2^{}, size: {}",
+                mask_style,
+                lg_accesses_per_element,
+                human_size(std::mem::size_of::<u64>() * data.len())
+            );
+            // Only LowBits and SpreadOut2x draw from 2^lg_domain_size values: HighBits keeps every bit above
+            // lg_domain_size and AllBits every bit, so their keys are nearly all distinct, and the map must fit them.
+            let capacity = match mask_style {
+                MaskStyle::LowBits | MaskStyle::SpreadOut2x => 1 << lg_domain_size,
+                MaskStyle::HighBits | MaskStyle::AllBits => data.len(),
+            };
+            let expected = OnceCell::new();
+            benchmark_occurrences("Occurrences by sorting (sort_unstable)", &data, &expected, repeats, || {
+                count_occurrences_by_sort(&data)
+            });
+            benchmark_hashed_occurrences(&data, capacity, &expected, repeats, HasherKind::MulSwapMul, MulSwapMulHasher);
+            let seeded_mul_swap_mul =
+                HASH_SEED.map_or_else(SeededMulSwapMulHasher::random, SeededMulSwapMulHasher::with_seed);
+            benchmark_hashed_occurrences(
+                &data,
+                capacity,
+                &expected,
+                repeats,
+                HasherKind::SeededMulSwapMul,
+                seeded_mul_swap_mul,
+            );
         }
     }
-    if !run_benchmark {
-        return;
+}
+
+/// Benchmarks the `count_occurrences` functions that hash, with hasher `h`.
+fn benchmark_hashed_occurrences<H: InvertibleU64Hasher>(
+    data: &[u64],
+    capacity: usize,
+    expected: &OnceCell<Vec<(u64, usize)>>,
+    repeats: usize,
+    kind: HasherKind,
+    h: H,
+) {
+    let hasher = kind.name();
+    benchmark_occurrences(&format!("Occurrences by HashMap (dense_table + {hasher})"), data, expected, repeats, || {
+        let mut map = U64CountMap::with_capacity_and_hasher(capacity, h);
+        count_occurrences_by_u64_hash(data, &mut map)
+    });
+    benchmark_occurrences(&format!("Occurrences by hashed sorting (dlsd + {hasher})"), data, expected, repeats, || {
+        count_occurrences_by_hashed_dlsd_sort(data, h)
+    });
+    let name = format!("Occurrences by hashed sorting (fused dlsd_and_count + {hasher})");
+    benchmark_occurrences(&name, data, expected, repeats, || count_occurrences_by_fused_hashed_dlsd_sort(data, h));
+}
+
+/// Benchmarks the `top_k` functions on Zipf-distributed words, for each exponent in `TOP_K_ZIPF_EXPONENTS`.
+fn top_k_sweep() {
+    let mut rng = fastrand::Rng::with_seed(0);
+    let repeats = 1usize << 25usize.saturating_sub(TOP_K_LG_SIZE);
+    let num_words = 1 << TOP_K_LG_DOMAIN_SIZE;
+    for &exponent in TOP_K_ZIPF_EXPONENTS {
+        let data = zipf_keys(&mut rng, 1 << TOP_K_LG_SIZE, num_words, exponent);
+        println!(
+            "top {}, Zipf exponent: {}, words drawn from: {}, size: {}",
+            TOP_K,
+            exponent,
+            num_words,
+            human_size(std::mem::size_of::<u64>() * data.len())
+        );
+        let expected = OnceCell::new();
+        benchmark_top_k("Top-k by sorting (sort_unstable)", &data, &expected, repeats, || top_k_by_sort(&data, TOP_K));
+        benchmark_hashed_top_k(&data, num_words, &expected, repeats, HasherKind::MulSwapMul, MulSwapMulHasher);
+        let seeded_mul_swap_mul =
+            HASH_SEED.map_or_else(SeededMulSwapMulHasher::random, SeededMulSwapMulHasher::with_seed);
+        benchmark_hashed_top_k(&data, num_words, &expected, repeats, HasherKind::SeededMulSwapMul, seeded_mul_swap_mul);
     }
-    // Warmup.
-    for _ in 0..repeats {
-        f();
+}
+
+/// Benchmarks the `top_k` functions that hash, with hasher `h`.
+fn benchmark_hashed_top_k<H: InvertibleU64Hasher>(
+    data: &[u64],
+    capacity: usize,
+    expected: &OnceCell<Vec<(u64, usize)>>,
+    repeats: usize,
+    kind: HasherKind,
+    h: H,
+) {
+    let hasher = kind.name();
+    benchmark_top_k(&format!("Top-k by HashMap (dense_table + {hasher})"), data, expected, repeats, || {
+        let mut map = U64CountMap::with_capacity_and_hasher(capacity, h);
+        top_k_by_u64_hash(data, TOP_K, &mut map)
+    });
+    let name = format!("Top-k by hashed sorting (fused dlsd_and_count + {hasher})");
+    benchmark_top_k(&name, data, expected, repeats, || top_k_by_hashed_dlsd_sort(data, TOP_K, h));
+    for &counters in TOP_K_SPACE_SAVING_COUNTERS {
+        let name = format!("Top-k by Space-Saving ({counters} counters, SwissTable + {hasher})");
+        if !is_selected(&name) {
+            continue;
+        }
+        let expected = expected.get_or_init(|| top_k_by_sort(data, TOP_K));
+        let mut space_saving = SpaceSaving::with_counters_and_hasher(counters, h);
+        let top_k = top_k_by_space_saving(data, TOP_K, &mut space_saving);
+        benchmark(&name, repeats, || top_k_by_space_saving(data, TOP_K, &mut space_saving));
+        let found = top_k.iter().filter(|(word, _)| expected.iter().any(|(expected_word, _)| word == expected_word));
+        println!(
+            "    found {} of the top {}, counts overestimated by at most {}",
+            found.count(),
+            TOP_K,
+            space_saving.max_overestimate()
+        );
     }
-    let start = Instant::now();
-    for _ in 0..repeats {
-        f();
+}
+
+/// Checks that `top_k` returns `expected` (computed on first use), then benchmarks it.
+fn benchmark_top_k(
+    name: &str,
+    data: &[u64],
+    expected: &OnceCell<Vec<(u64, usize)>>,
+    repeats: usize,
+    mut top_k: impl FnMut() -> Vec<(u64, usize)>,
+) {
+    if !is_selected(name) {
+        return;
     }
-    let duration = start.elapsed();
-    println!("  {}: {}", name, human_time(repeats, duration));
+    let expected = expected.get_or_init(|| top_k_by_sort(data, TOP_K));
+    if top_k() != *expected {
+        println!("  {name}: returned different words or counts than sorting");
+    }
+    benchmark(name, repeats, top_k);
 }
 
 fn human_time(repeats: usize, duration: Duration) -> String {
@@ -230,14 +619,14 @@ fn human_size(size: usize) -> String {
     let mut size = size as f64;
     size /= 1024.0;
     if size < 1024.0 {
-        return format!("{}KiB", size);
+        return format!("{:.1}KiB", size);
     }
     size /= 1024.0;
     if size < 1024.0 {
-        return format!("{}MiB", size);
+        return format!("{:.1}MiB", size);
     }
     size /= 1024.0;
-    format!("{}GiB", size)
+    format!("{:.1}GiB", size)
 }
 
 fn main() {
@@ -247,6 +636,21 @@ fn main() {
         "mask style: {:?}, average accesses per element: 2^{}, prefetch distance: {}",
         mask_style, lg_accesses_per_element, PREFETCH_DISTANCE
     );
+    let caches = cache_sizes()
+        .into_iter()
+        .map(|(name, bytes)| format!("{} {}", name, human_size(bytes)))
+        .collect::<Vec<_>>();
+    println!("caches: {}", if caches.is_empty() { "unknown".to_string() } else { caches.join(", ") });
+
+    if HASH_QUALITY_REPORT {
+        hash_quality_report::print_report();
+    }
+    if OCCURRENCES_SWEEP {
+        occurrences_sweep();
+    }
+    if TOP_K_SWEEP {
+        top_k_sweep();
+    }
 
     // let num_threads = rayon::current_num_threads();
     let num_threads = 1;
@@ -254,23 +658,9 @@ fn main() {
 
     let mut rng = fastrand::Rng::with_seed(0);
     for &lg_size in SIZES {
-        let mut data = vec![0u64; 1 << lg_size];
         let lg_domain_size = lg_size.saturating_sub(lg_accesses_per_element);
         let domain_size = 1usize << lg_domain_size;
-        let mask = match mask_style {
-            MaskStyle::LowBits => (1u64 << lg_domain_size) - 1,
-            MaskStyle::HighBits => (1u64 << lg_domain_size).wrapping_neg(),
-            MaskStyle::SpreadOut2x => ((1u64 << (2 * lg_domain_size)) - 1) & 0x5555_5555_5555_5555,
-            MaskStyle::AllBits => u64::MAX,
-        };
-        for d in &mut data {
-            let random = rng.u64(..);
-            let mut masked = random & mask;
-            if matches!(mask_style, MaskStyle::SpreadOut2x) {
-                masked = masked | (masked << 1);
-            }
-            *d = masked;
-        }
+        let mut data = random_keys(&mut rng, 1 << lg_size, lg_domain_size, mask_style);
 
         let repeats = 1usize << 25usize.saturating_sub(lg_size);
         println!(
@@ -307,161 +697,39 @@ fn main() {
             std::hint::black_box(sum);
         });
 
-        // For smaller benchmarks, we run all benchmarks. For larger benchmarks, we only run
-        // the algorithms that are at least a certain speed.
-        // let is_smaller = lg_size <= 25;
-        let is_smaller = true;
         // Don't run NoOp hashing for huge sizes when the data is unfavorable to it; it takes forever.
         let noop_will_finish = lg_size < 25 || matches!(mask_style, MaskStyle::LowBits);
         let noop_will_be_fast = lg_size < 20 || matches!(mask_style, MaskStyle::LowBits);
 
-        let sip_hasher = RandomState::new(); // Unfortunately not seedable :(
-        let murmur_hasher = BuildHasherDefault::<U64Hasher<MurmurHasher>>::default();
-        let mulswapmul_hasher = BuildHasherDefault::<U64Hasher<MulSwapMulHasher>>::default();
-        let foldhash_hasher = FoldRandomState::default();
-
-        if is_smaller {
-            benchmark("HashSet (SwissTable + SipHash)", repeats, || {
-                count_unique_by_hash(&data, sip_hasher.clone(), domain_size);
-            });
-
-            benchmark("HashSet (SwissTable + Murmur)", repeats, || {
-                count_unique_by_hash(&data, murmur_hasher.clone(), domain_size);
-            });
-
-            benchmark("HashSet (SwissTable + FoldHash)", repeats, || {
-                count_unique_by_hash(&data, foldhash_hasher.clone(), domain_size);
-            });
-        }
-
-        benchmark("HashSet (SwissTable + MulSwapMul)", repeats, || {
-            count_unique_by_hash(&data, mulswapmul_hasher.clone(), domain_size);
-        });
-
-        if is_smaller {
-            if noop_will_finish {
-                let noop_hasher = BuildHasherDefault::<U64Hasher<NoopHasher>>::default();
-                benchmark(
-                    "HashSet (SwissTable + NoOp)",
-                    if noop_will_be_fast { repeats } else { 1 },
-                    || {
-                        count_unique_by_hash(&data, noop_hasher.clone(), domain_size);
-                    },
-                );
+        // Computed on first use, so that sizes with every benchmark filtered out don't pay for it.
+        let expected = OnceCell::new();
+        let mut counters = registry(domain_size, HASH_SEED, num_threads);
+        counters.extend(benchmark_only_counters(domain_size, HASH_SEED));
+        // Group by method, serial first, keeping the registry's order within each group.
+        counters.sort_by_key(|counter| (counter.is_parallel(), counter.method() as u8));
+        let mut best_fixed: Option<(String, Duration)> = None;
+        for counter in &mut counters {
+            let mut repeats = repeats;
+            if counter.hasher() == Some(HasherKind::NoOp) && counter.method() == CountingMethod::HashSet {
+                if !noop_will_finish {
+                    continue;
+                }
+                if !noop_will_be_fast {
+                    repeats = 1;
+                }
             }
-
-            benchmark("HashSet (dense_table + Murmur)", repeats, || {
-                count_unique_by_u64_hash::<MurmurHasher>(&data, domain_size);
-            });
-
-            if noop_will_finish {
-                benchmark(
-                    "HashSet (dense_table + NoOp)",
-                    if noop_will_be_fast { repeats } else { 1 },
-                    || {
-                        count_unique_by_u64_hash::<NoopHasher>(&data, domain_size);
-                    },
-                );
+            let duration = benchmark_counter(counter.as_mut(), &data, &expected, repeats);
+            if let Some(duration) = duration
+                && !counter.is_parallel()
+                && best_fixed.as_ref().is_none_or(|(_, best)| duration < *best)
+            {
+                best_fixed = Some((counter.name(), duration));
             }
         }
-
-        benchmark("HashSet (dense_table + MulSwapMul)", repeats, || {
-            count_unique_by_u64_hash::<MulSwapMulHasher>(&data, domain_size);
-        });
-
-        if is_smaller {
-            benchmark("Sorting (merge sort)", repeats, || {
-                count_unique_by_sort(&data, |v| v.sort());
-            });
-
-            benchmark("Sorting (quick sort)", repeats, || {
-                count_unique_by_sort(&data, |v| v.sort_unstable());
-            });
-        }
-
-        benchmark("Sorting (radix sort)", repeats, || {
-            count_unique_by_sort(&data, |v| v.voracious_sort());
-        });
-
-        // benchmark("Sorting (dlsd sort)", repeats, || {
-        //     count_unique_by_sort(&data, |v| dlsd_sort(v));
-        // });
-
-        benchmark("Sorting (wide merge sort)", repeats, || {
-            count_unique_by_sort(&data, |v| wide_merge_sort(v));
-        });
-
-        if is_smaller {
-            benchmark("Hashed sorting (radix + Murmur)", repeats, || {
-                count_unique_by_hashed_sort::<MurmurHasher>(&data);
-            });
-            benchmark("Hashed sorting (radix + NoOp)", repeats, || {
-                count_unique_by_hashed_sort::<NoopHasher>(&data);
-            });
+        benchmark_automatic(&data, &expected, repeats, best_fixed);
+        if DEDUP_BENCHMARKS {
+            benchmark_dedups(&data, domain_size, repeats);
+            benchmark_stable_dedups(&data, domain_size, repeats);
         }
-
-        benchmark("Hashed sorting (radix + MulSwapMul)", repeats, || {
-            count_unique_by_hashed_sort::<MulSwapMulHasher>(&data);
-        });
-
-        benchmark("Hashed sorting (dlsd + MulSwapMul)", repeats, || {
-            count_unique_by_hashed_dlsd_sort::<MulSwapMulHasher>(&data);
-        });
-
-        benchmark("Hashed sorting (fused dlsd_and_count + MulSwapMul)", repeats, || {
-            count_unique_by_fused_hashed_dlsd_sort::<MulSwapMulHasher>(&data);
-        });
-
-        // Parallel benchmarks
-        if is_smaller {
-            benchmark("Parallel HashSet (dashmap + SipHash)", repeats, || {
-                count_unique_by_parallel_hash(&data, sip_hasher.clone(), domain_size);
-            });
-
-            benchmark("Parallel HashSet (dashmap + Murmur)", repeats, || {
-                count_unique_by_parallel_hash(&data, murmur_hasher.clone(), domain_size);
-            });
-        }
-
-        benchmark("Parallel HashSet (dashmap + FoldHash)", repeats, || {
-            count_unique_by_parallel_hash(&data, foldhash_hasher.clone(), domain_size);
-        });
-
-        if is_smaller {
-            benchmark("Parallel HashSet (scc + SipHash)", repeats, || {
-                count_unique_by_scc_parallel_hash(&data, sip_hasher.clone(), domain_size);
-            });
-
-            benchmark("Parallel HashSet (scc + Murmur)", repeats, || {
-                count_unique_by_scc_parallel_hash(&data, murmur_hasher.clone(), domain_size);
-            });
-        }
-
-        benchmark("Parallel HashSet (scc + FoldHash)", repeats, || {
-            count_unique_by_scc_parallel_hash(&data, foldhash_hasher.clone(), domain_size);
-        });
-
-        if is_smaller {
-            benchmark("Parallel sorting (merge sort)", repeats, || {
-                count_unique_by_parallel_sort(&data, |v| v.par_sort());
-            });
-
-            benchmark("Parallel sorting (quick sort)", repeats, || {
-                count_unique_by_parallel_sort(&data, |v| v.par_sort_unstable());
-            });
-        }
-
-        benchmark("Parallel sorting (radix sort)", repeats, || {
-            count_unique_by_parallel_sort(&data, |v| v.voracious_mt_sort(num_threads));
-        });
-        benchmark(
-            "Parallel hashed sorting (radix + MulSwapMul)",
-            repeats,
-            || {
-                count_unique_by_hashed_parallel_sort::<MulSwapMulHasher>(&data, |v| {
-                    v.voracious_mt_sort(num_threads)
-                });
-            },
-        );
     }
 }
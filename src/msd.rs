@@ -0,0 +1,243 @@
+//! In-place MSD radix sort (American flag sort) for hashed keys.
+//!
+//! Unlike `dlsd_sort`, which deals between two full-size buffers, this partitions the hashed copy in place by the top
+//! digit, then recurses into each bucket on the next digit. The only extra memory beyond the hashed copy is a
+//! histogram per recursion level. The trade-off is that in-place partitioning does random swaps rather than
+//! streaming writes.
+
+use crate::counters::count_unique_in_sorted;
+use crate::hashers::SeededU64Hasher;
+use crate::uninit::{HASH_BLOCK, uninit_words};
+use std::mem::MaybeUninit;
+
+const LG_RADIX: u32 = 8;
+const RADIX: usize = 1 << LG_RADIX;
+const WORD_BITS: u32 = 64;
+/// Buckets at most this size are finished with `sort_unstable` rather than another partitioning pass.
+const SMALL_BUCKET: usize = 64;
+
+/// Returns the hashes of `orig_data` under `hasher`, sorted.
+pub fn msd_sort<Hasher: SeededU64Hasher>(orig_data: &[u64], hasher: Hasher) -> Vec<u64> {
+    let len = orig_data.len();
+    let mut data = Vec::with_capacity(len);
+    msd_sort_with_scratch(orig_data, hasher, &mut data.spare_capacity_mut()[..len]);
+    // Safety: `msd_sort_with_scratch` wrote all `len` words.
+    unsafe { data.set_len(len) };
+    data
+}
+
+/// Like `msd_sort`, but writes the sorted hashes to `data` without allocating. Returns `data`, now initialized.
+///
+/// `data` must be the same length as `orig_data`. It may be uninitialized, and its contents on entry are ignored.
+pub fn msd_sort_with_scratch<'a, Hasher: SeededU64Hasher>(
+    orig_data: &[u64],
+    hasher: Hasher,
+    data: &'a mut [MaybeUninit<u64>],
+) -> &'a mut [u64] {
+    let (data, counts) = hash_and_count(orig_data, hasher, data);
+    sort_recursive(data, &counts, WORD_BITS - LG_RADIX);
+    data
+}
+
+/// Sorts words that are already hashed, in place. For callers that pack more than the hash into each word, which the
+/// sort then can't compute itself.
+pub(crate) fn sort_hashed_in_place(data: &mut [u64]) {
+    let shift = WORD_BITS - LG_RADIX;
+    sort_recursive(data, &histogram(data, shift), shift);
+}
+
+/// Like `msd_sort` followed by counting unique values, but counts each leaf bucket as soon as it is sorted, so the
+/// sorted array is never scanned a second time.
+pub fn msd_sort_and_count<Hasher: SeededU64Hasher>(orig_data: &[u64], hasher: Hasher) -> usize {
+    msd_sort_and_count_with_scratch(orig_data, hasher, &mut uninit_words(orig_data.len()))
+}
+
+/// Like `msd_sort_and_count`, but uses the caller's `data` as scratch space, without allocating.
+///
+/// `data` must be the same length as `orig_data`. It may be uninitialized, and its contents on entry are ignored, so
+/// it can be reused across calls.
+pub fn msd_sort_and_count_with_scratch<Hasher: SeededU64Hasher>(
+    orig_data: &[u64],
+    hasher: Hasher,
+    data: &mut [MaybeUninit<u64>],
+) -> usize {
+    let (data, counts) = hash_and_count(orig_data, hasher, data);
+    count_recursive(data, &counts, WORD_BITS - LG_RADIX)
+}
+
+/// Hashes `orig_data` into `data`, returning it as initialized along with the histogram of the top digit.
+fn hash_and_count<'a, Hasher: SeededU64Hasher>(
+    orig_data: &[u64],
+    hasher: Hasher,
+    data: &'a mut [MaybeUninit<u64>],
+) -> (&'a mut [u64], [usize; RADIX]) {
+    assert!(data.len() == orig_data.len());
+    let shift = WORD_BITS - LG_RADIX;
+    let mut counts = [0usize; RADIX];
+    let mut hashes = [0u64; HASH_BLOCK];
+    // Hash a block at a time into `hashes`, then histogram it and copy it out while it's still in L1.
+    for (block, data_block) in orig_data.chunks(HASH_BLOCK).zip(data.chunks_mut(HASH_BLOCK)) {
+        let hashes = &mut hashes[..block.len()];
+        hasher.hash_batch(block, hashes);
+        data_block.write_copy_of_slice(hashes);
+        for &h in hashes.iter() {
+            unsafe {
+                *counts.get_unchecked_mut(read_radix(h, shift)) += 1;
+            }
+        }
+    }
+    // Safety: every word was hashed into `data` above.
+    (unsafe { data.assume_init_mut() }, counts)
+}
+
+/// Sorts `data`, given the histogram of its digit at `shift`.
+fn sort_recursive(data: &mut [u64], counts: &[usize; RADIX], shift: u32) {
+    let bucket_starts = partition(data, counts, |word| read_radix(word, shift));
+    for i in 0..RADIX {
+        let bucket = &mut data[bucket_starts[i]..bucket_starts[i] + counts[i]];
+        if shift == 0 {
+            // All digits are consumed, so every word in the bucket is equal.
+            continue;
+        }
+        if bucket.len() <= SMALL_BUCKET {
+            bucket.sort_unstable();
+            continue;
+        }
+        let next_shift = shift - LG_RADIX;
+        sort_recursive(bucket, &histogram(bucket, next_shift), next_shift);
+    }
+}
+
+/// Returns the number of unique values in `data`, given the histogram of its digit at `shift`.
+fn count_recursive(data: &mut [u64], counts: &[usize; RADIX], shift: u32) -> usize {
+    let bucket_starts = partition(data, counts, |word| read_radix(word, shift));
+    let mut unique_count = 0;
+    for i in 0..RADIX {
+        let bucket = &mut data[bucket_starts[i]..bucket_starts[i] + counts[i]];
+        if bucket.len() <= 1 || shift == 0 {
+            unique_count += !bucket.is_empty() as usize;
+            continue;
+        }
+        if bucket.len() <= SMALL_BUCKET {
+            bucket.sort_unstable();
+            unique_count += count_unique_in_sorted(bucket);
+            continue;
+        }
+        let next_shift = shift - LG_RADIX;
+        unique_count += count_recursive(bucket, &histogram(bucket, next_shift), next_shift);
+    }
+    unique_count
+}
+
+fn histogram(data: &[u64], shift: u32) -> [usize; RADIX] {
+    let mut counts = [0usize; RADIX];
+    for &word in data {
+        unsafe {
+            *counts.get_unchecked_mut(read_radix(word, shift)) += 1;
+        }
+    }
+    counts
+}
+
+/// Permutes `data` in place so that words are grouped by `digit`, in increasing digit order, given the histogram
+/// `counts` of `digit` over `data`. Returns the start of each bucket. Also used by `dlsd_sort_and_count_lean`.
+pub(crate) fn partition<const RADIX: usize>(
+    data: &mut [u64],
+    counts: &[usize; RADIX],
+    digit: impl Fn(u64) -> usize,
+) -> [usize; RADIX] {
+    let mut bucket_starts = [0usize; RADIX];
+    let mut pos = 0;
+    for i in 0..RADIX {
+        bucket_starts[i] = pos;
+        pos += counts[i];
+    }
+    // Cycle each misplaced word to the head of its destination bucket, carrying the displaced word onwards.
+    let mut heads = bucket_starts;
+    for bucket in 0..RADIX {
+        let end = bucket_starts[bucket] + counts[bucket];
+        while heads[bucket] < end {
+            let mut word = data[heads[bucket]];
+            loop {
+                let radix = digit(word);
+                if radix == bucket {
+                    break;
+                }
+                debug_assert!(radix < RADIX);
+                let head = unsafe { heads.get_unchecked_mut(radix) };
+                std::mem::swap(&mut word, unsafe { data.get_unchecked_mut(*head) });
+                *head += 1;
+            }
+            data[heads[bucket]] = word;
+            heads[bucket] += 1;
+        }
+    }
+    bucket_starts
+}
+
+#[inline(always)]
+fn read_radix(word: u64, shift: u32) -> usize {
+    const MASK: u64 = (1 << LG_RADIX) - 1;
+    ((word >> shift) & MASK) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashers::{MulSwapMulHasher, NoopHasher};
+
+    /// Checks `msd_sort` and `msd_sort_and_count` against `sort_unstable` and `dedup` on the hashes of `data`.
+    fn check(data: &[u64], hasher: impl SeededU64Hasher) {
+        let mut expected: Vec<u64> = data.iter().map(|&word| hasher.hash(word)).collect();
+        expected.sort_unstable();
+        assert_eq!(msd_sort(data, hasher), expected, "len {}", data.len());
+        expected.dedup();
+        assert_eq!(msd_sort_and_count(data, hasher), expected.len(), "len {}", data.len());
+    }
+
+    #[test]
+    fn random_lengths() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        // Around `SMALL_BUCKET` and one more than a full split, up to buckets that recurse twice.
+        for len in [0, 1, 63, 64, 65, 257, 1 << 20] {
+            for domain in [len as u64 / 4 + 1, u64::MAX] {
+                let data: Vec<u64> = (0..len).map(|_| rng.u64(..domain)).collect();
+                check(&data, MulSwapMulHasher);
+            }
+        }
+    }
+
+    #[test]
+    fn all_equal() {
+        // Every bucket but one is empty, all the way down to the last digit.
+        for word in [0, 42, u64::MAX] {
+            check(&vec![word; 100_000], NoopHasher);
+        }
+    }
+
+    #[test]
+    fn high_bits_only() {
+        // Unhashed, so the top digit splits them and every later digit is zero.
+        let mut rng = fastrand::Rng::with_seed(2);
+        let data: Vec<u64> = (0..100_000).map(|_| rng.u64(..1 << 12) << 52).collect();
+        check(&data, NoopHasher);
+    }
+
+    #[test]
+    fn partition_groups_by_digit() {
+        let mut rng = fastrand::Rng::with_seed(3);
+        let mut data: Vec<u64> = (0..1000).map(|_| rng.u64(..100)).collect();
+        let mut expected = data.clone();
+        expected.sort_unstable();
+        let mut counts = [0; 4];
+        for &word in &data {
+            counts[word as usize % 4] += 1;
+        }
+        let bucket_starts = partition(&mut data, &counts, |word| word as usize % 4);
+        assert_eq!(bucket_starts, [0, counts[0], counts[0] + counts[1], counts[0] + counts[1] + counts[2]]);
+        assert!(data.iter().map(|word| word % 4).is_sorted());
+        // And is a permutation.
+        data.sort_unstable();
+        assert_eq!(data, expected);
+    }
+}
@@ -0,0 +1,121 @@
+//! Optional per-phase timing for the sorting algorithms.
+//!
+//! Compiled out unless the `phase-timing` feature is enabled: `phase` then returns a zero-sized guard that does
+//! nothing, and `take` always returns no phases. When enabled, each guard adds its lifetime to its thread's total for
+//! its phase, and `benchmark` prints the calling thread's totals as a breakdown table.
+//!
+//! Only the calling thread's phases are reported, so the parallel sorts show up as their phases on that thread (e.g.
+//! `par_wide_merge_sort: merge`), each timed in wall time. Phases their workers record are left out: summed across
+//! threads, they would add up to more than the total.
+//!
+//! Phases should be coarse (a whole pass, a whole merge), since recording one searches the thread's totals.
+
+use std::time::Duration;
+
+/// A phase name, plus an index for phases that repeat (e.g. the pass number or the recursion depth).
+pub type PhaseKey = (&'static str, Option<usize>);
+
+#[cfg(feature = "phase-timing")]
+mod imp {
+    use super::PhaseKey;
+    use std::cell::RefCell;
+    use std::time::{Duration, Instant};
+
+    thread_local! {
+        static TOTALS: RefCell<Vec<(PhaseKey, Duration)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    pub struct PhaseGuard {
+        key: PhaseKey,
+        start: Instant,
+    }
+
+    #[inline(always)]
+    pub fn phase(name: &'static str, index: Option<usize>) -> PhaseGuard {
+        PhaseGuard {
+            key: (name, index),
+            start: Instant::now(),
+        }
+    }
+
+    impl Drop for PhaseGuard {
+        fn drop(&mut self) {
+            let elapsed = self.start.elapsed();
+            TOTALS.with_borrow_mut(|totals| match totals.iter_mut().find(|(key, _)| *key == self.key) {
+                Some((_, total)) => *total += elapsed,
+                None => totals.push((self.key, elapsed)),
+            });
+        }
+    }
+
+    pub fn take() -> Vec<(PhaseKey, Duration)> {
+        TOTALS.take()
+    }
+}
+
+#[cfg(not(feature = "phase-timing"))]
+mod imp {
+    use super::PhaseKey;
+    use std::time::Duration;
+
+    pub struct PhaseGuard;
+
+    #[inline(always)]
+    pub fn phase(_name: &'static str, _index: Option<usize>) -> PhaseGuard {
+        PhaseGuard
+    }
+
+    // Matches the enabled guard, so that call sites can end a phase early with `drop`.
+    impl Drop for PhaseGuard {
+        #[inline(always)]
+        fn drop(&mut self) {}
+    }
+
+    pub fn take() -> Vec<(PhaseKey, Duration)> {
+        Vec::new()
+    }
+}
+
+pub use imp::PhaseGuard;
+
+/// Starts timing a phase. The time until the returned guard is dropped is added to the phase's total.
+#[inline(always)]
+pub fn phase(name: &'static str) -> PhaseGuard {
+    imp::phase(name, None)
+}
+
+/// Like `phase`, for one of a numbered series of phases.
+#[inline(always)]
+pub fn indexed_phase(name: &'static str, index: usize) -> PhaseGuard {
+    imp::phase(name, Some(index))
+}
+
+/// Returns the accumulated totals of the phases recorded on this thread, in first-recorded order, and resets them.
+pub fn take() -> Vec<(PhaseKey, Duration)> {
+    imp::take()
+}
+
+#[cfg(all(test, feature = "phase-timing"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_returns_nested_and_indexed_phases_of_this_thread() {
+        take();
+        {
+            let _outer = phase("outer");
+            for i in 0..2 {
+                let _inner = indexed_phase("inner", i);
+            }
+            let _repeat = indexed_phase("inner", 0);
+        }
+        std::thread::spawn(|| drop(phase("other thread"))).join().unwrap();
+
+        let phases = take();
+        let keys: Vec<PhaseKey> = phases.iter().map(|&(key, _)| key).collect();
+        assert_eq!(keys, [("inner", Some(0)), ("inner", Some(1)), ("outer", None)]);
+        let [inner_0, inner_1, outer] = [0, 1, 2].map(|i| phases[i].1);
+        assert!(outer >= inner_0 + inner_1, "outer {outer:?}, inner {inner_0:?} + {inner_1:?}");
+        assert!(take().is_empty());
+    }
+}
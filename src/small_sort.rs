@@ -0,0 +1,260 @@
+//! Sorting-network base case for `wide_merge_sort`.
+//!
+//! With AVX-512, sorts the merge sort's small leaves without data-dependent branches. First each block of 16 words is
+//! sorted by a 16-input sorting network, eight blocks at a time, one per lane. Then the blocks are merged pairwise,
+//! ping-ponging between the array and the auxiliary buffer, using a bitonic merge of two vectors at each step.
+//!
+//! Selected at compile time: without AVX-512 (e.g. when not building with `-C target-cpu=native`, as `run.sh` does)
+//! this falls back to `sort_unstable`. AVX2 versions of the same network and merges were no faster than
+//! `sort_unstable`, so there are none.
+
+use std::mem::MaybeUninit;
+
+/// Sorts `data`. If `write_to_aux` is true, writes the result to `aux` instead, leaving `data` clobbered. Otherwise
+/// may clobber `aux`.
+///
+/// `aux` must be the same length as `data`, and may be uninitialized. If `write_to_aux` is true, all of it is written.
+pub fn sort(data: &mut [u64], aux: &mut [MaybeUninit<u64>], write_to_aux: bool) {
+    assert!(aux.len() == data.len());
+    #[cfg(all(target_arch = "x86_64", target_feature = "avx512f"))]
+    avx512::sort(data, aux, write_to_aux);
+
+    #[cfg(not(all(target_arch = "x86_64", target_feature = "avx512f")))]
+    {
+        data.sort_unstable();
+        if write_to_aux {
+            aux.write_copy_of_slice(data);
+        }
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "avx512f"))]
+mod avx512 {
+    use crate::uninit::as_uninit_mut;
+    use std::arch::x86_64::*;
+    use std::mem::MaybeUninit;
+
+    /// Number of inputs of the sorting network, and so the length of the sorted runs it produces.
+    const BLOCK: usize = 16;
+    /// Words per vector.
+    const WIDTH: usize = 8;
+
+    pub fn sort(data: &mut [u64], aux: &mut [MaybeUninit<u64>], write_to_aux: bool) {
+        let len = data.len();
+
+        // Each merge pass doubles the run length and swaps buffers. Pick where the network writes its runs so that
+        // the last pass lands in the requested buffer.
+        let mut merge_passes = 0;
+        while BLOCK << merge_passes < len {
+            merge_passes += 1;
+        }
+        let runs_in_aux = write_to_aux == (merge_passes % 2 == 0);
+        // Derive each pointer once, before any writes: deriving a second pointer from `data` would invalidate the
+        // first, and reading through it afterwards is undefined behavior.
+        let (data_ptr, aux_ptr) = (data.as_mut_ptr(), aux.as_mut_ptr() as *mut u64);
+        // Each buffer is read only once a pass has written all of it.
+        let (mut from, mut to) = if runs_in_aux {
+            sort_blocks(data_ptr as *const u64, aux_ptr, len);
+            // Safety: the network wrote every word of `aux`, and the merge passes write to `data` only words they
+            // read.
+            (unsafe { aux.assume_init_mut() }, unsafe { as_uninit_mut(data) })
+        } else {
+            sort_blocks(data_ptr as *const u64, data_ptr, len);
+            (data, aux)
+        };
+
+        let mut width = BLOCK;
+        while width < len {
+            merge_pass(from, to, width);
+            // Safety: the pass wrote every word of `to`, and the next one writes to `from` only words it read.
+            (from, to) = (unsafe { to.assume_init_mut() }, unsafe { as_uninit_mut(from) });
+            width *= 2;
+        }
+    }
+
+    /// Reads `len` words from `src` and writes them to `dst` as sorted runs of `BLOCK` words (the last one possibly
+    /// shorter). `src` and `dst` may be the same.
+    #[inline(always)]
+    fn sort_blocks(src: *const u64, dst: *mut u64, len: usize) {
+        // Each group of `BLOCK` vectors is a BLOCK x WIDTH matrix. The network sorts its columns, which are then
+        // written out as consecutive runs.
+        let group = BLOCK * WIDTH;
+        let full_len = len - len % group;
+        let mut rows = [0u64; BLOCK * WIDTH];
+        for start in (0..full_len).step_by(group) {
+            unsafe {
+                let mut v: [__m512i; BLOCK] =
+                    std::array::from_fn(|r| _mm512_loadu_si512(src.add(start + r * WIDTH) as *const __m512i));
+                network(&mut v);
+                for (r, &row) in v.iter().enumerate() {
+                    _mm512_storeu_si512(rows.as_mut_ptr().add(r * WIDTH) as *mut __m512i, row);
+                }
+                for column in 0..WIDTH {
+                    for r in 0..BLOCK {
+                        *dst.add(start + column * BLOCK + r) = rows[r * WIDTH + column];
+                    }
+                }
+            }
+        }
+        // Sort the leftover words as a whole. Every `BLOCK`-aligned piece of a sorted range is itself a sorted run.
+        let tail = unsafe {
+            if !std::ptr::eq(src, dst) {
+                std::ptr::copy_nonoverlapping(src.add(full_len), dst.add(full_len), len - full_len);
+            }
+            std::slice::from_raw_parts_mut(dst.add(full_len), len - full_len)
+        };
+        tail.sort_unstable();
+    }
+
+    /// Evaluates a 16-input sorting network (Green's, 60 comparators in 10 layers) on each lane.
+    #[inline(always)]
+    fn network(v: &mut [__m512i; BLOCK]) {
+        macro_rules! layer {
+            ($(($a:literal, $b:literal)),*) => {
+                $((v[$a], v[$b]) = unsafe { (_mm512_min_epu64(v[$a], v[$b]), _mm512_max_epu64(v[$a], v[$b])) };)*
+            };
+        }
+        layer!((0, 13), (1, 12), (2, 15), (3, 14), (4, 8), (5, 6), (7, 11), (9, 10));
+        layer!((0, 5), (1, 7), (2, 9), (3, 4), (6, 13), (8, 14), (10, 15), (11, 12));
+        layer!((0, 1), (2, 3), (4, 5), (6, 8), (7, 9), (10, 11), (12, 13), (14, 15));
+        layer!((0, 2), (1, 3), (4, 10), (5, 11), (6, 7), (8, 9), (12, 14), (13, 15));
+        layer!((1, 2), (3, 12), (4, 6), (5, 7), (8, 10), (9, 11), (13, 14));
+        layer!((1, 4), (2, 6), (5, 8), (7, 10), (9, 13), (11, 14));
+        layer!((2, 4), (3, 6), (9, 12), (11, 13));
+        layer!((3, 5), (6, 8), (7, 9), (10, 12));
+        layer!((3, 4), (5, 6), (7, 8), (9, 10), (11, 12));
+        layer!((6, 7), (8, 9));
+    }
+
+    /// Merges each pair of adjacent `width`-word runs of `src` into `dst`, writing all of it.
+    fn merge_pass(src: &[u64], dst: &mut [MaybeUninit<u64>], width: usize) {
+        let len = src.len();
+        let full_len = len - len % (2 * width);
+        for start in (0..full_len).step_by(2 * width) {
+            let (a, b) = src[start..start + 2 * width].split_at(width);
+            merge_vectors(a, b, &mut dst[start..start + 2 * width]);
+        }
+        // Only the last pair can be uneven.
+        if full_len < len {
+            let mid = (full_len + width).min(len);
+            merge_runs(&src[full_len..mid], &src[mid..len], &mut dst[full_len..len]);
+        }
+    }
+
+    /// Merges the equal-length sorted runs `a` and `b` into `dst`, a vector at a time.
+    ///
+    /// Keeps the largest `WIDTH` words seen so far in a vector. Each step loads the next vector from whichever run has
+    /// the smaller head, and splits it and the kept vector into the smallest `WIDTH` words, which are final, and the
+    /// largest, which are kept. Lengths must be multiples of `WIDTH`.
+    #[inline(always)]
+    fn merge_vectors(a: &[u64], b: &[u64], dst: &mut [MaybeUninit<u64>]) {
+        assert!(a.len() == b.len() && a.len().is_multiple_of(WIDTH) && !a.is_empty() && dst.len() == a.len() + b.len());
+        let load = |ptr: *const u64| unsafe { _mm512_loadu_si512(ptr as *const __m512i) };
+        let store = |ptr: *mut u64, v: __m512i| unsafe { _mm512_storeu_si512(ptr as *mut __m512i, v) };
+        unsafe {
+            let (mut a_ptr, mut b_ptr) = (a.as_ptr(), b.as_ptr());
+            let (a_end, b_end) = (a_ptr.add(a.len()), b_ptr.add(b.len()));
+            let mut dst_ptr = dst.as_mut_ptr() as *mut u64;
+            let (low, mut high) = merge_two(load(a_ptr), load(b_ptr));
+            store(dst_ptr, low);
+            (a_ptr, b_ptr, dst_ptr) = (a_ptr.add(WIDTH), b_ptr.add(WIDTH), dst_ptr.add(WIDTH));
+            while a_ptr < a_end && b_ptr < b_end {
+                let take_a = *a_ptr <= *b_ptr;
+                let next = std::hint::select_unpredictable(take_a, a_ptr, b_ptr);
+                a_ptr = a_ptr.add(take_a as usize * WIDTH);
+                b_ptr = b_ptr.add(!take_a as usize * WIDTH);
+                let low;
+                (low, high) = merge_two(load(next), high);
+                store(dst_ptr, low);
+                dst_ptr = dst_ptr.add(WIDTH);
+            }
+            // One run is used up; the rest of the other follows in order.
+            let (mut rest, rest_end) = if a_ptr < a_end { (a_ptr, a_end) } else { (b_ptr, b_end) };
+            while rest < rest_end {
+                let low;
+                (low, high) = merge_two(load(rest), high);
+                store(dst_ptr, low);
+                (rest, dst_ptr) = (rest.add(WIDTH), dst_ptr.add(WIDTH));
+            }
+            store(dst_ptr, high);
+        }
+    }
+
+    /// Given two sorted vectors, returns the smallest and the largest half of their words, each sorted.
+    #[inline(always)]
+    fn merge_two(x: __m512i, y: __m512i) -> (__m512i, __m512i) {
+        // `x` followed by reversed `y` is bitonic, so the lane-wise min and max split it into two bitonic halves.
+        unsafe {
+            let y = _mm512_permutexvar_epi64(_mm512_set_epi64(0, 1, 2, 3, 4, 5, 6, 7), y);
+            (sort_bitonic(_mm512_min_epu64(x, y)), sort_bitonic(_mm512_max_epu64(x, y)))
+        }
+    }
+
+    /// Sorts the lanes of `x`, given that they form a bitonic sequence.
+    #[inline(always)]
+    fn sort_bitonic(mut x: __m512i) -> __m512i {
+        // Compare-exchange lanes 4 apart, then 2, then 1. The upper lane of each pair keeps the max.
+        unsafe {
+            for (partners, upper) in [
+                (_mm512_set_epi64(3, 2, 1, 0, 7, 6, 5, 4), 0b1111_0000),
+                (_mm512_set_epi64(5, 4, 7, 6, 1, 0, 3, 2), 0b1100_1100),
+                (_mm512_set_epi64(6, 7, 4, 5, 2, 3, 0, 1), 0b1010_1010),
+            ] {
+                let y = _mm512_permutexvar_epi64(partners, x);
+                x = _mm512_mask_blend_epi64(upper, _mm512_min_epu64(x, y), _mm512_max_epu64(x, y));
+            }
+        }
+        x
+    }
+
+    /// Branchless two-way merge of `a` and `b` into `dst`.
+    fn merge_runs(a: &[u64], b: &[u64], dst: &mut [MaybeUninit<u64>]) {
+        debug_assert!(a.len() + b.len() == dst.len());
+        let (mut i, mut j, mut k) = (0, 0, 0);
+        unsafe {
+            while i < a.len() && j < b.len() {
+                let (x, y) = (*a.get_unchecked(i), *b.get_unchecked(j));
+                let take_a = x <= y;
+                dst.get_unchecked_mut(k).write(std::hint::select_unpredictable(take_a, x, y));
+                i += take_a as usize;
+                j += !take_a as usize;
+                k += 1;
+            }
+        }
+        dst[k..k + a.len() - i].write_copy_of_slice(&a[i..]);
+        k += a.len() - i;
+        dst[k..].write_copy_of_slice(&b[j..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uninit::uninit_words;
+
+    /// Lengths around the block size, the vector-merge group size, and up to the 1024-word base case.
+    const LENGTHS: &[usize] = &[0, 1, 2, 7, 8, 9, 15, 16, 17, 31, 33, 127, 128, 129, 255, 257, 500, 1000, 1023, 1024];
+
+    fn check(data: &[u64]) {
+        let mut expected = data.to_vec();
+        expected.sort_unstable();
+        for write_to_aux in [false, true] {
+            let mut sorted = data.to_vec();
+            let mut aux = uninit_words(data.len());
+            sort(&mut sorted, &mut aux, write_to_aux);
+            let result = if write_to_aux { unsafe { aux.assume_init_ref() } } else { &sorted[..] };
+            assert_eq!(result, expected, "len {}, write_to_aux {}", data.len(), write_to_aux);
+        }
+    }
+
+    #[test]
+    fn matches_sort_unstable() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        for &len in LENGTHS {
+            check(&(0..len).map(|_| rng.u64(..)).collect::<Vec<_>>());
+            check(&(0..len).map(|_| rng.u64(..4)).collect::<Vec<_>>());
+            check(&(0..len).map(|_| u64::MAX - rng.u64(..2)).collect::<Vec<_>>());
+            check(&(0..len as u64).rev().collect::<Vec<_>>());
+        }
+    }
+}
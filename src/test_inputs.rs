@@ -0,0 +1,19 @@
+//! Inputs shared by the tests of `dedup_unique`, `count_occurrences` and `top_k`.
+
+/// Seeds for the seeded hashers, fixed so that a failure can be reproduced. Each input takes the next one.
+pub(crate) const SEEDS: [u64; 4] = [0, 1, 0x0123_4567_89ab_cdef, u64::MAX];
+
+/// Empty and all-equal inputs, then random words from small and large domains, with 0 and u64::MAX mixed in. Those
+/// are the words the hash tables and the unhashing have to get right. The largest inputs are big enough that a table
+/// created with a capacity of 16 has to grow.
+pub(crate) fn inputs() -> Vec<Vec<u64>> {
+    let mut rng = fastrand::Rng::with_seed(47);
+    let mut inputs = vec![vec![], vec![0; 1000], vec![u64::MAX; 1000], vec![0, u64::MAX, 0, 1, u64::MAX, 0]];
+    for (len, domain) in [(1, u64::MAX), (1000, 10), (100_000, 1000), (100_000, u64::MAX)] {
+        let mut data: Vec<u64> = (0..len).map(|_| rng.u64(..domain)).collect();
+        data.extend([0, u64::MAX].repeat(len / 10));
+        rng.shuffle(&mut data);
+        inputs.push(data);
+    }
+    inputs
+}
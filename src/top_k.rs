@@ -0,0 +1,247 @@
+//! Finding the k most frequent words of a slice (its heavy hitters), with their counts.
+//!
+//! The exact versions count every distinct word with a `count_occurrences` function and keep the k largest counts, so
+//! they cost about as much as counting. `SpaceSaving` instead only tracks a fixed number of words in one pass, which
+//! is approximate but needs memory proportional to that number rather than to the number of distinct words.
+//!
+//! Results are ordered by decreasing count, with ties broken by the smaller word, so every exact version returns the
+//! same words.
+
+use crate::count_occurrences::{
+    count_occurrences_by_fused_hashed_dlsd_sort, count_occurrences_by_sort, count_occurrences_by_u64_hash,
+};
+use crate::hashers::{BuildU64Hasher, InvertibleU64Hasher, SeededU64Hasher};
+use crate::u64_count_map::U64CountMap;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Returns the `k` most frequent words of `data` with their counts, with `count_occurrences_by_sort`. The baseline for
+/// the others.
+pub fn top_k_by_sort(data: &[u64], k: usize) -> Vec<(u64, usize)> {
+    most_frequent(count_occurrences_by_sort(data), k)
+}
+
+/// Returns the `k` most frequent words of `data` with their counts, by counting them in `map`, which is cleared first.
+pub fn top_k_by_u64_hash<H: SeededU64Hasher>(data: &[u64], k: usize, map: &mut U64CountMap<H>) -> Vec<(u64, usize)> {
+    most_frequent(count_occurrences_by_u64_hash(data, map), k)
+}
+
+/// Returns the `k` most frequent words of `data` with their counts, by counting runs of equal hashes while sorting them
+/// with `dlsd_sort_and_count_occurrences`.
+pub fn top_k_by_hashed_dlsd_sort<H: InvertibleU64Hasher>(data: &[u64], k: usize, hasher: H) -> Vec<(u64, usize)> {
+    most_frequent(count_occurrences_by_fused_hashed_dlsd_sort(data, hasher), k)
+}
+
+/// Returns the approximate `k` most frequent words of `data` with their estimated counts, by adding them to
+/// `space_saving`, which is cleared first. See `SpaceSaving` for how far off they can be.
+pub fn top_k_by_space_saving<H: SeededU64Hasher>(
+    data: &[u64],
+    k: usize,
+    space_saving: &mut SpaceSaving<H>,
+) -> Vec<(u64, usize)> {
+    space_saving.clear();
+    for &d in data {
+        space_saving.add(d);
+    }
+    space_saving.top_k(k)
+}
+
+/// Keeps the `k` pairs of `occurrences` with the largest counts, and sorts them by decreasing count, then word.
+pub fn most_frequent(mut occurrences: Vec<(u64, usize)>, k: usize) -> Vec<(u64, usize)> {
+    let by_frequency = |&(word, count): &(u64, usize)| (Reverse(count), word);
+    if k < occurrences.len() {
+        occurrences.select_nth_unstable_by_key(k, by_frequency);
+        occurrences.truncate(k);
+    }
+    occurrences.sort_unstable_by_key(by_frequency);
+    occurrences
+}
+
+/// The Space-Saving algorithm (Metwally et al., 2005): tracks up to a fixed number of words with a counter each, and
+/// when a new word arrives with all counters taken, gives it the smallest counter, incremented.
+///
+/// So a word's count can only be overestimated, by at most `max_overestimate`, which is no more than the number of
+/// words added divided by the number of counters. Any word occurring more often than that is tracked.
+pub struct SpaceSaving<H: SeededU64Hasher> {
+    /// (count, word) of each counter. A counter keeps its index when it is taken over.
+    counters: Vec<(usize, u64)>,
+    /// Indices into `counters`, as a min-heap on count, so the root is the counter to take over.
+    heap: Vec<usize>,
+    /// Index in `heap` of each counter.
+    heap_positions: Vec<usize>,
+    /// Index in `counters` of each tracked word.
+    indices: HashMap<u64, usize, BuildU64Hasher<H>>,
+    max_counters: usize,
+}
+
+impl<H: SeededU64Hasher> SpaceSaving<H> {
+    /// Returns an empty summary with `counters` counters, indexing its words with `hasher`.
+    pub fn with_counters_and_hasher(counters: usize, hasher: H) -> Self {
+        assert!(counters > 0);
+        Self {
+            counters: Vec::with_capacity(counters),
+            heap: Vec::with_capacity(counters),
+            heap_positions: Vec::with_capacity(counters),
+            indices: HashMap::with_capacity_and_hasher(counters, BuildU64Hasher(hasher)),
+            max_counters: counters,
+        }
+    }
+
+    /// Removes all words, keeping the allocations.
+    pub fn clear(&mut self) {
+        self.counters.clear();
+        self.heap.clear();
+        self.heap_positions.clear();
+        self.indices.clear();
+    }
+
+    /// Adds one occurrence of `word`.
+    #[inline(always)]
+    pub fn add(&mut self, word: u64) {
+        if let Some(&i) = self.indices.get(&word) {
+            self.counters[i].0 += 1;
+            self.sift_down(self.heap_positions[i]);
+        } else if self.counters.len() < self.max_counters {
+            // Counters are only taken over once all of them are in use, so until then each word gets a new one.
+            let i = self.counters.len();
+            self.counters.push((1, word));
+            self.heap.push(i);
+            self.heap_positions.push(i);
+            self.indices.insert(word, i);
+            self.sift_up(i);
+        } else {
+            let i = self.heap[0];
+            let (min_count, evicted) = self.counters[i];
+            self.indices.remove(&evicted);
+            self.indices.insert(word, i);
+            self.counters[i] = (min_count + 1, word);
+            self.sift_down(0);
+        }
+    }
+
+    /// Returns how much any count returned by `top_k` can exceed the true count: the smallest counter once all of them
+    /// are taken, zero before that.
+    pub fn max_overestimate(&self) -> usize {
+        if self.counters.len() < self.max_counters { 0 } else { self.count_at(0) }
+    }
+
+    /// Returns the `k` tracked words with the largest counts, sorted like `most_frequent`.
+    pub fn top_k(&self, k: usize) -> Vec<(u64, usize)> {
+        most_frequent(self.counters.iter().map(|&(count, word)| (word, count)).collect(), k)
+    }
+
+    /// Returns the count of the counter at heap position `position`.
+    #[inline(always)]
+    fn count_at(&self, position: usize) -> usize {
+        self.counters[self.heap[position]].0
+    }
+
+    /// Moves the counter at heap position `position` up until its parent is no larger.
+    fn sift_up(&mut self, mut position: usize) {
+        while position > 0 {
+            let parent = (position - 1) / 2;
+            if self.count_at(parent) <= self.count_at(position) {
+                break;
+            }
+            self.swap(position, parent);
+            position = parent;
+        }
+    }
+
+    /// Moves the counter at heap position `position` down until its children are no smaller.
+    fn sift_down(&mut self, mut position: usize) {
+        loop {
+            let left = 2 * position + 1;
+            if left >= self.heap.len() {
+                break;
+            }
+            let right = left + 1;
+            let child =
+                if right < self.heap.len() && self.count_at(right) < self.count_at(left) { right } else { left };
+            if self.count_at(position) <= self.count_at(child) {
+                break;
+            }
+            self.swap(position, child);
+            position = child;
+        }
+    }
+
+    /// Swaps two heap positions, keeping `heap_positions` up to date.
+    #[inline(always)]
+    fn swap(&mut self, a: usize, b: usize) {
+        self.heap.swap(a, b);
+        self.heap_positions[self.heap[a]] = a;
+        self.heap_positions[self.heap[b]] = b;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashers::{MulSwapMulHasher, MurmurHasher, SeededMulSwapMulHasher};
+    use crate::test_inputs::{SEEDS, inputs};
+    use crate::workloads::zipf_keys;
+
+    fn true_counts(data: &[u64]) -> HashMap<u64, usize> {
+        let mut counts = HashMap::new();
+        for &d in data {
+            *counts.entry(d).or_default() += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn exact_versions_match_sort() {
+        let mut map = U64CountMap::<MurmurHasher>::with_capacity(16);
+        let mut rng = fastrand::Rng::with_seed(50);
+        // Word i occurs i % 4 + 1 times, so each count is shared by a quarter of the words, and ties are broken
+        // across the whole range, 0 and u64::MAX included.
+        let mut ties: Vec<u64> =
+            (0..1000u64).flat_map(|i| [i.wrapping_mul(0x9e37_79b9_7f4a_7c15)].repeat(i as usize % 4 + 1)).collect();
+        ties.extend([u64::MAX; 4]);
+        rng.shuffle(&mut ties);
+        let mut inputs = inputs();
+        inputs.push(ties);
+        for (data, seed) in inputs.into_iter().zip(SEEDS.into_iter().cycle()) {
+            let hasher = SeededMulSwapMulHasher::with_seed(seed);
+            let mut expected = true_counts(&data).into_iter().collect::<Vec<_>>();
+            expected.sort_unstable_by_key(|&(word, count)| (Reverse(count), word));
+            let distinct = expected.len();
+            for k in [0, 1, 3, 100, distinct, distinct + 1] {
+                let context = format!("len {}, k {k}", data.len());
+                let by_sort = top_k_by_sort(&data, k);
+                assert_eq!(by_sort, expected[..k.min(distinct)], "{context}");
+                assert_eq!(top_k_by_u64_hash(&data, k, &mut map), by_sort, "u64_hash, {context}");
+                assert_eq!(top_k_by_hashed_dlsd_sort(&data, k, hasher), by_sort, "dlsd, {context}");
+            }
+        }
+    }
+
+    #[test]
+    fn space_saving_bounds_hold_on_zipf() {
+        let mut rng = fastrand::Rng::with_seed(51);
+        let len = 200_000;
+        for exponent in [0.8, 1.2] {
+            let data = zipf_keys(&mut rng, len, 10_000, exponent);
+            let counts = true_counts(&data);
+            for num_counters in [10, 100, 1000] {
+                let context = format!("exponent {exponent}, {num_counters} counters");
+                let mut space_saving = SpaceSaving::with_counters_and_hasher(num_counters, MulSwapMulHasher);
+                let tracked = top_k_by_space_saving(&data, num_counters, &mut space_saving);
+                let max_overestimate = space_saving.max_overestimate();
+                assert!(max_overestimate <= len / num_counters, "{context}");
+                for &(word, estimate) in &tracked {
+                    let count = counts[&word];
+                    assert!(count <= estimate && estimate <= count + max_overestimate, "word {word}, {context}");
+                }
+                for (&word, &count) in &counts {
+                    if count > len / num_counters {
+                        let is_tracked = tracked.iter().any(|&(tracked_word, _)| tracked_word == word);
+                        assert!(is_tracked, "word {word}, {context}");
+                    }
+                }
+                assert!(space_saving.top_k(0).is_empty(), "{context}");
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! A dense_hash_map from u64 keys to their number of occurrences.
+//!
+//! Same layout as `U64HashSet`: open addressing over cache-line buckets, zero marking empty slots, and no metadata
+//! table. Each bucket holds 4 keys followed by their 4 counts, so a probe still touches a single cache line.
+
+use crate::dense_table::{self, DenseTable};
+use crate::hashers::SeededU64Hasher;
+
+/// A map from u64 keys to how many times each was added, hashed with `H`.
+pub struct U64CountMap<H: SeededU64Hasher> {
+    table: DenseTable<Bucket, H>,
+    zero_count: usize,
+}
+
+const BUCKET_SIZE: usize = 4;
+
+#[derive(Clone, Copy)]
+#[repr(align(64))] // Cache line alignment
+struct Bucket {
+    keys: [u64; BUCKET_SIZE],
+    counts: [usize; BUCKET_SIZE],
+}
+
+impl dense_table::Bucket for Bucket {
+    const SLOTS: usize = BUCKET_SIZE;
+    // Counts start at zero, so `add` increments a new key's count like any other.
+    const EMPTY: Self = Bucket { keys: [0; BUCKET_SIZE], counts: [0; BUCKET_SIZE] };
+
+    #[inline(always)]
+    fn key(&self, slot: usize) -> u64 {
+        self.keys[slot]
+    }
+
+    #[inline(always)]
+    fn key_mut(&mut self, slot: usize) -> &mut u64 {
+        &mut self.keys[slot]
+    }
+
+    #[inline(always)]
+    fn copy_value(&mut self, slot: usize, from: &Self, from_slot: usize) {
+        self.counts[slot] = from.counts[from_slot];
+    }
+}
+
+impl<H: SeededU64Hasher + Default> U64CountMap<H> {
+    /// Returns an empty map with room for `capacity` keys.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, H::default())
+    }
+}
+
+impl<H: SeededU64Hasher> U64CountMap<H> {
+    /// Like `with_capacity`, but hashing with `hasher`, e.g. a seeded one.
+    ///
+    /// Grows like `U64HashSet`: it holds `capacity` keys without growing, and past that `add` doubles the table
+    /// whenever it gets more than half full.
+    ///
+    /// Panics if that many slots would overflow `usize`.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: H) -> Self {
+        Self {
+            table: DenseTable::with_capacity_and_hasher(capacity, hasher),
+            zero_count: 0,
+        }
+    }
+
+    /// Removes all keys, keeping the allocation so the map can be reused without allocating.
+    pub fn clear(&mut self) {
+        self.table.clear();
+        self.zero_count = 0;
+    }
+
+    /// Returns the number of distinct keys in the map.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.table.len() + (self.zero_count > 0) as usize
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns each key in the map with its count, in table order. Scans the whole table, so costs time proportional
+    /// to its capacity.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, usize)> + '_ {
+        let zero = (self.zero_count > 0).then_some((0, self.zero_count));
+        let nonzero = self.table.buckets().iter().flat_map(|bucket| bucket.keys.into_iter().zip(bucket.counts));
+        zero.into_iter().chain(nonzero.filter(|&(key, _)| key != 0))
+    }
+
+    /// Prefetches the bucket `key` would go in, to `add` it a little later without waiting on a cache miss.
+    #[inline(always)]
+    pub fn prefetch(&mut self, key: u64) {
+        self.table.prefetch(key);
+    }
+
+    /// Adds one occurrence of `key`.
+    #[inline(always)]
+    pub fn add(&mut self, key: u64) {
+        if key == 0 {
+            // Zero marks empty slots, so it is counted on the side.
+            self.zero_count += 1;
+            return;
+        }
+        self.table.find_or_insert(key, |bucket, slot| bucket.counts[slot] += 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashers::MurmurHasher;
+
+    #[test]
+    fn counts_survive_growth() {
+        let mut map = U64CountMap::<MurmurHasher>::with_capacity(1);
+        for key in 0..1000 {
+            for _ in 0..key % 3 + 1 {
+                map.add(key);
+            }
+        }
+        assert_eq!(map.len(), 1000);
+        let mut counts: Vec<(u64, usize)> = map.iter().collect();
+        counts.sort_unstable();
+        assert!(counts.into_iter().eq((0..1000).map(|key| (key, key as usize % 3 + 1))));
+    }
+}
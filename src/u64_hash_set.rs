@@ -5,12 +5,12 @@
 //! we may need to do longer probe sequences (each probe is 8 bytes, not 1 byte), but on the other hand we only take
 //! 1 cache miss per access, not 2.
 
-use crate::hashers::StatelessU64Hasher;
+use crate::dense_table::{self, DenseTable};
+use crate::hashers::SeededU64Hasher;
 
-pub struct U64HashSet<H: StatelessU64Hasher> {
-    table: Box<[Bucket]>,
-    len: usize,
-    marker: std::marker::PhantomData<H>,
+/// A set of u64 keys, hashed with `H`.
+pub struct U64HashSet<H: SeededU64Hasher> {
+    table: DenseTable<Bucket, H>,
     has_zero: bool,
 }
 
@@ -20,61 +20,126 @@ const BUCKET_SIZE: usize = 8;
 #[repr(align(64))] // Cache line alignment
 struct Bucket([u64; BUCKET_SIZE]);
 
-impl<H: StatelessU64Hasher> U64HashSet<H> {
+impl dense_table::Bucket for Bucket {
+    const SLOTS: usize = BUCKET_SIZE;
+    const EMPTY: Self = Bucket([0; BUCKET_SIZE]);
+
+    #[inline(always)]
+    fn key(&self, slot: usize) -> u64 {
+        self.0[slot]
+    }
+
+    #[inline(always)]
+    fn key_mut(&mut self, slot: usize) -> &mut u64 {
+        &mut self.0[slot]
+    }
+
+    #[inline(always)]
+    fn copy_value(&mut self, _slot: usize, _from: &Self, _from_slot: usize) {}
+}
+
+impl<H: SeededU64Hasher + Default> U64HashSet<H> {
+    /// Returns an empty set with room for `capacity` keys.
     pub fn with_capacity(capacity: usize) -> Self {
-        // TODO: integer overflow...
-        let num_buckets = (capacity.next_power_of_two() * 2).div_ceil(BUCKET_SIZE);
-        let table = vec![Bucket([0u64; BUCKET_SIZE]); num_buckets].into_boxed_slice();
+        Self::with_capacity_and_hasher(capacity, H::default())
+    }
+}
+
+impl<H: SeededU64Hasher> U64HashSet<H> {
+    /// Like `with_capacity`, but hashing with `hasher`, e.g. a seeded one.
+    ///
+    /// The table has at least twice `capacity` slots, so it holds `capacity` keys without growing. Past that, `insert`
+    /// doubles it whenever it gets more than half full, rehashing every key.
+    ///
+    /// Panics if that many slots would overflow `usize`.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: H) -> Self {
         Self {
-            table,
-            len: 0,
-            marker: std::marker::PhantomData,
+            table: DenseTable::with_capacity_and_hasher(capacity, hasher),
             has_zero: false,
         }
     }
 
+    /// Removes all elements, keeping the allocation so the set can be reused without allocating.
+    pub fn clear(&mut self) {
+        self.table.clear();
+        self.has_zero = false;
+    }
+
+    /// Returns the number of keys in the set.
     #[inline(always)]
     pub fn len(&self) -> usize {
-        self.len + self.has_zero as usize
+        self.table.len() + self.has_zero as usize
     }
 
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the keys in the set, in table order. Scans the whole table, so costs time proportional to its capacity.
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        let zero = self.has_zero.then_some(0);
+        zero.into_iter().chain(self.table.buckets().iter().flat_map(|bucket| bucket.0).filter(|&key| key != 0))
+    }
+
+    /// Prefetches the bucket `key` would go in, to `insert` it a little later without waiting on a cache miss.
     #[inline(always)]
     pub fn prefetch(&mut self, key: u64) {
-        let hash64 = H::hash(key);
-        let bucket_mask = self.table.len() - 1;
-        let bucket_i = hash64 as usize;
-        // Safety: bucket_mask is correct because the number of buckets is a power of 2.
-        unsafe {std::intrinsics::prefetch_write_data(self.table.get_unchecked(bucket_i & bucket_mask) as *const Bucket as *const u8, 0)};
+        self.table.prefetch(key);
     }
 
+    /// Adds `key` to the set, if it isn't already there. Returns whether it wasn't.
     #[inline(always)]
-    pub fn insert(&mut self, key: u64) {
+    pub fn insert(&mut self, key: u64) -> bool {
         if key == 0 {
-            self.len += !self.has_zero as usize;
+            // Zero marks empty slots, so it is tracked on the side, and counted by `len`.
+            let is_new = !self.has_zero;
             self.has_zero = true;
-            return;
+            return is_new;
         }
-        let hash64 = H::hash(key);
-        let bucket_mask = self.table.len() - 1;
-        let element_offset_in_bucket = (hash64 >> 61) as usize;
-        let mut bucket_i = hash64 as usize;
-
-
-        loop {
-            // Safety: bucket_mask is correct because the number of buckets is a power of 2.
-            let bucket = unsafe { self.table.get_unchecked_mut(bucket_i & bucket_mask) };
-            for element_i in 0..BUCKET_SIZE {
-                let element = &mut bucket.0[(element_i + element_offset_in_bucket) % BUCKET_SIZE];
-                if *element == 0 {
-                    *element = key;
-                    self.len += 1;
-                    return;
-                }
-                if *element == key {
-                    return;
-                }
+        self.table.find_or_insert(key, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashers::MurmurHasher;
+
+    #[test]
+    fn zero_counts_once() {
+        let mut set = U64HashSet::<MurmurHasher>::with_capacity(16);
+        assert!(set.insert(0));
+        assert!(!set.insert(0));
+        assert!(set.insert(1));
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.iter().collect::<Vec<_>>(), [0, 1]);
+        set.clear();
+        assert!(set.is_empty());
+        assert!(set.insert(0));
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn grows_past_capacity() {
+        for capacity in [0, 1, 100] {
+            let mut set = U64HashSet::<MurmurHasher>::with_capacity(capacity);
+            for key in 0..10_000 {
+                assert!(set.insert(key));
             }
-            bucket_i += 1;
+            for key in 0..10_000 {
+                assert!(!set.insert(key));
+            }
+            assert_eq!(set.len(), 10_000);
+            let mut keys: Vec<u64> = set.iter().collect();
+            keys.sort_unstable();
+            assert!(keys.into_iter().eq(0..10_000));
         }
     }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn capacity_overflow_panics() {
+        U64HashSet::<MurmurHasher>::with_capacity(usize::MAX);
+    }
 }
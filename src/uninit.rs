@@ -0,0 +1,57 @@
+//! Helpers for the scratch buffers the `_with_scratch` functions take, which may be uninitialized.
+//!
+//! The algorithms write every word of a buffer before reading it, so they never need it zeroed. They write through
+//! `MaybeUninit::write`, and only view a range as `&[u64]` once all of it has been written.
+
+use crate::hashers::SeededU64Hasher;
+use std::mem::MaybeUninit;
+
+/// Words hashed at a time into a buffer on the stack, which then gets copied out or histogrammed while it is still in
+/// L1. Shared by every sort that hashes its input, so their block sizes can't drift apart.
+pub(crate) const HASH_BLOCK: usize = 256;
+
+/// Returns `len` uninitialized words, to pass as scratch.
+pub(crate) fn uninit_words(len: usize) -> Box<[MaybeUninit<u64>]> {
+    Box::new_uninit_slice(len)
+}
+
+/// Grows `buffer` to at least `len` words, without initializing them. Reallocates rather than resizing in place, since
+/// there is nothing to copy.
+pub(crate) fn grow(buffer: &mut Vec<MaybeUninit<u64>>, len: usize) {
+    if buffer.len() < len {
+        *buffer = uninit_words(len).into_vec();
+    }
+}
+
+/// Views initialized values as possibly uninitialized, to write to them where scratch is expected. `T: Copy`, so
+/// nothing is leaked by overwriting them.
+///
+/// # Safety
+///
+/// The caller must write only initialized values through the returned slice. `values` is still typed as initialized
+/// once the borrow ends, so writing `MaybeUninit::uninit()` would leave it holding uninitialized memory.
+pub(crate) unsafe fn as_uninit_mut<T: Copy>(values: &mut [T]) -> &mut [MaybeUninit<T>] {
+    // Safety: same layout, and `MaybeUninit<T>` is valid wherever `T` is.
+    unsafe { &mut *(values as *mut [T] as *mut [MaybeUninit<T>]) }
+}
+
+/// Hashes `values` into `hashes`, which must be the same length, and returns them as initialized.
+///
+/// `hash_batch` writes to `&mut [u64]`, which uninitialized memory can't be viewed as, so this hashes a block at a
+/// time into a buffer on the stack and copies it out.
+#[inline(always)]
+pub(crate) fn hash_batch_into<'a, H: SeededU64Hasher>(
+    hasher: H,
+    values: &[u64],
+    hashes: &'a mut [MaybeUninit<u64>],
+) -> &'a mut [u64] {
+    assert!(values.len() == hashes.len());
+    let mut block = [0u64; HASH_BLOCK];
+    for (values, hashes) in values.chunks(HASH_BLOCK).zip(hashes.chunks_mut(HASH_BLOCK)) {
+        let block = &mut block[..values.len()];
+        hasher.hash_batch(values, block);
+        hashes.write_copy_of_slice(block);
+    }
+    // Safety: every word was written above.
+    unsafe { hashes.assume_init_mut() }
+}
@@ -1,129 +1,593 @@
+use crate::counters::count_unique_in_sorted;
+use crate::phase_timing;
+use crate::small_sort;
+use crate::uninit::{as_uninit_mut, uninit_words};
+use rayon::prelude::*;
+use std::mem::MaybeUninit;
+
+/// Default fan-in of the merge: 256 runs merged at once.
 const N: usize = 256;
+/// Number of independent output ranges per thread that the parallel sort splits its final merge into.
+const MERGE_PARTS_PER_THREAD: usize = 4;
+/// Largest supported tournament tree depth, i.e. log2 of the largest fan-in.
+const MAX_LG_FAN_IN: usize = 12;
 
-#[inline(always)]
-fn merge256(mut srcs: [std::slice::Iter<u64>; N], dst: &mut [u64]) {
-    // Head of each list. u64::MAX sentinel if the list is exhausted.
-    let mut keys: [u64; N] = std::array::from_fn(|i| srcs[i].next().copied().unwrap_or(u64::MAX));
+/// Index of a run within a merge. Kept as small as possible so the loser table stays compact.
+pub trait RunIndex: Copy {
+    fn from_usize(i: usize) -> Self;
+    fn to_usize(self) -> usize;
+}
+
+impl RunIndex for u8 {
+    #[inline(always)]
+    fn from_usize(i: usize) -> Self {
+        i as u8
+    }
+
+    #[inline(always)]
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+impl RunIndex for u16 {
+    #[inline(always)]
+    fn from_usize(i: usize) -> Self {
+        i as u16
+    }
+
+    #[inline(always)]
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+/// Marker for a supported merge fan-in, selecting the narrowest index type that can name every run.
+pub struct FanIn<const N: usize>;
+
+pub trait SupportedFanIn {
+    type Index: RunIndex;
+}
 
-    // Tournament tree for the merge. loser_table[0] is the winner; loser_table[i] for i>0 is the loser of the match at that node of the tournament.
+macro_rules! supported_fan_in {
+    ($index:ty: $($n:literal),*) => {
+        $(impl SupportedFanIn for FanIn<$n> {
+            type Index = $index;
+        })*
+    };
+}
+
+supported_fan_in!(u8: 2, 4, 8, 16, 32, 64, 128, 256);
+supported_fan_in!(u16: 512, 1024, 2048, 4096);
+
+type Index<const F: usize> = <FanIn<F> as SupportedFanIn>::Index;
+
+/// A key that a `LoserTree` orders its runs by, smallest first.
+pub(crate) trait TreeKey: Copy {
+    /// Whether `self` wins its match against `other`, i.e. comes out of the tree first. On a tie, `other` wins.
+    fn beats(self, other: Self) -> bool;
+}
+
+impl TreeKey for u64 {
+    #[inline(always)]
+    fn beats(self, other: Self) -> bool {
+        self < other
+    }
+}
+
+/// Tournament tree of losers over the keys of the heads of `F` runs. The caller owns the runs: it reads the winning
+/// run's head, and replaces the winner's key with that of the run's next head.
+///
+/// Exhausted runs need a key that loses to every live key, or at least ties with the largest. For example `MergeTree`
+/// uses u64::MAX, and `wide_merge_sort_by_key` pairs each key with a rank that puts exhausted runs last.
+pub(crate) struct LoserTree<K: TreeKey, const F: usize>
+where
+    FanIn<F>: SupportedFanIn,
+{
+    // Key of the head of each run.
+    keys: [K; F],
+    // loser_table[0] is the winner; loser_table[i] for i>0 is the loser of the match at that node of the tournament.
     //
-    // "Losing" a tournament means you are a *bigger* u64.
-    let mut loser_table: [u8; N] = [0; N];
+    // "Losing" a tournament means you are a *bigger* key.
+    loser_table: [Index<F>; F],
+}
 
+impl<K: TreeKey, const F: usize> LoserTree<K, F>
+where
+    FanIn<F>: SupportedFanIn,
+{
+    /// Builds the tree over the keys of the runs' first heads.
+    #[inline(always)]
+    pub fn new(keys: [K; F]) -> Self {
+        debug_assert!(F.is_power_of_two() && F.trailing_zeros() as usize <= MAX_LG_FAN_IN);
+        let mut loser_table: [Index<F>; F] = [Index::<F>::from_usize(0); F];
 
-    //// Initialize loser table, leaves upwards towards the root. We maintain a temporary array of winners.
-    {
-        let mut winners: [u8; N * 2] = std::array::from_fn(|i| (i % N) as u8);
-        for i in (1..N).rev() {
-            let left_child = 2 * i;
-            let right_child = left_child + 1;
-            let winner = if keys[winners[left_child] as usize] < keys[winners[right_child] as usize] { left_child } else { right_child };
-            winners[i] = winners[winner];
-            let loser = winner ^ 1;
-            loser_table[i] = winners[loser];
+        //// Initialize loser table, leaves upwards towards the root. We maintain a temporary array of winners for the
+        //// internal nodes; the winner at leaf node F + i is just run i.
+        let mut winners: [Index<F>; F] = [Index::<F>::from_usize(0); F];
+        let winner_at = |winners: &[Index<F>; F], node: usize| if node >= F { node - F } else { winners[node].to_usize() };
+        for i in (1..F).rev() {
+            let left = winner_at(&winners, 2 * i);
+            let right = winner_at(&winners, 2 * i + 1);
+            let (winner, loser) = if keys[left].beats(keys[right]) { (left, right) } else { (right, left) };
+            winners[i] = Index::<F>::from_usize(winner);
+            loser_table[i] = Index::<F>::from_usize(loser);
         }
         loser_table[0] = winners[1];
+
+        Self { keys, loser_table }
     }
 
-    for d in dst {
-        // Advance winner.
-        let mut winner_i = loser_table[0] as usize;
-        *d = keys[winner_i];
-        keys[winner_i] = srcs[winner_i].next().copied().unwrap_or(u64::MAX);
+    /// Returns the index of the run with the smallest head.
+    #[inline(always)]
+    pub fn winner(&self) -> usize {
+        self.loser_table[0].to_usize()
+    }
 
-        // Update loser table.
+    /// Returns the key of run `i`'s head.
+    #[inline(always)]
+    pub fn key(&self, i: usize) -> K {
+        self.keys[i]
+    }
+
+    /// Replaces the winner's key with `key`, that of its run's next head, and replays its matches up to the root.
+    #[inline(always)]
+    pub fn replace_winner(&mut self, key: K) {
+        let Self { keys, loser_table } = self;
+        // Depth of the tree. Constant per instantiation, so the per-level loops below are fully unrolled.
+        let lg_f = F.trailing_zeros() as usize;
+
+        let mut winner_i = loser_table[0].to_usize();
+        keys[winner_i] = key;
+
+        // Update loser table. Level 0 is the leaf's parent; level lg_f - 1 is the root.
 
         // Look up all the loser indices on the path to the root.
-        let leaf_i = winner_i + N;  // [N, 2N)
-        let mut loser7_i = loser_table[leaf_i >> 1] as usize;   // [N/2, N)
-        let mut loser6_i = loser_table[leaf_i >> 2] as usize;   // [N/4, N/2)
-        let mut loser5_i = loser_table[leaf_i >> 3] as usize;
-        let mut loser4_i = loser_table[leaf_i >> 4] as usize;
-        let mut loser3_i = loser_table[leaf_i >> 5] as usize;
-        let mut loser2_i = loser_table[leaf_i >> 6] as usize;
-        let mut loser1_i = loser_table[leaf_i >> 7] as usize;
-        let mut loser0_i = loser_table[1] as usize;  // We know it's at the root; no need to compute its address dynamically
-        // Look up the loser values.
-        let loser7 = keys[loser7_i];
-        let loser6 = keys[loser6_i];
-        let loser5 = keys[loser5_i];
-        let loser4 = keys[loser4_i];
-        let loser3 = keys[loser3_i];
-        let loser2 = keys[loser2_i];
-        let loser1 = keys[loser1_i];
-        let loser0 = keys[loser0_i];
+        let leaf_i = winner_i + F;  // [F, 2F)
+        let mut loser_is = [0usize; MAX_LG_FAN_IN];
+        for level in 0..lg_f {
+            loser_is[level] = loser_table[leaf_i >> (level + 1)].to_usize();
+        }
+        // Look up the loser keys up front, so the replay is a chain of selects.
+        let mut losers = [key; MAX_LG_FAN_IN];
+        for level in 0..lg_f {
+            losers[level] = keys[loser_is[level]];
+        }
 
         // Replay the new entrant against all losers on the path.
-        let mut winner = keys[winner_i];
-        (winner_i, winner, loser7_i) = if winner < loser7 { (winner_i, winner, loser7_i) } else { (loser7_i, loser7, winner_i) };
-        (winner_i, winner, loser6_i) = if winner < loser6 { (winner_i, winner, loser6_i) } else { (loser6_i, loser6, winner_i) };
-        (winner_i, winner, loser5_i) = if winner < loser5 { (winner_i, winner, loser5_i) } else { (loser5_i, loser5, winner_i) };
-        (winner_i, winner, loser4_i) = if winner < loser4 { (winner_i, winner, loser4_i) } else { (loser4_i, loser4, winner_i) };
-        (winner_i, winner, loser3_i) = if winner < loser3 { (winner_i, winner, loser3_i) } else { (loser3_i, loser3, winner_i) };
-        (winner_i, winner, loser2_i) = if winner < loser2 { (winner_i, winner, loser2_i) } else { (loser2_i, loser2, winner_i) };
-        (winner_i, winner, loser1_i) = if winner < loser1 { (winner_i, winner, loser1_i) } else { (loser1_i, loser1, winner_i) };
-        (winner_i, winner, loser0_i) = if winner < loser0 { (winner_i, winner, loser0_i) } else { (loser0_i, loser0, winner_i) };
+        let mut winner = key;
+        for level in 0..lg_f {
+            (winner_i, winner, loser_is[level]) = if winner.beats(losers[level]) {
+                (winner_i, winner, loser_is[level])
+            } else {
+                (loser_is[level], losers[level], winner_i)
+            };
+        }
         _ = winner;  // Unused
 
         // Update loser table.
-        loser_table[leaf_i >> 1] = loser7_i as u8;
-        loser_table[leaf_i >> 2] = loser6_i as u8;
-        loser_table[leaf_i >> 3] = loser5_i as u8;
-        loser_table[leaf_i >> 4] = loser4_i as u8;
-        loser_table[leaf_i >> 5] = loser3_i as u8;
-        loser_table[leaf_i >> 6] = loser2_i as u8;
-        loser_table[leaf_i >> 7] = loser1_i as u8;
-        loser_table[1] = loser0_i as u8;
-        loser_table[0] = winner_i as u8;
+        for level in 0..lg_f {
+            loser_table[leaf_i >> (level + 1)] = Index::<F>::from_usize(loser_is[level]);
+        }
+        loser_table[0] = Index::<F>::from_usize(winner_i);
+    }
+}
+
+/// Merges `F` sorted runs of u64s with a `LoserTree`, yielding their elements in sorted order. The runs can be any
+/// iterators, e.g. slices in memory or buffered readers over files.
+///
+/// Exhausted runs are represented by a u64::MAX sentinel, which ties with real u64::MAX keys. So once `pop` returns
+/// u64::MAX, the tree may have picked an exhausted run over a live one, and its state says nothing more about which
+/// elements are left. Callers must stop popping at that point. This is still enough to merge correctly: every key
+/// popped before then is smaller than the sentinel, so it came from a live run; and at that point every remaining key
+/// is at least u64::MAX, so all of them are u64::MAX.
+pub(crate) struct MergeTree<S: Iterator<Item = u64>, const F: usize>
+where
+    FanIn<F>: SupportedFanIn,
+{
+    srcs: [S; F],
+    tree: LoserTree<u64, F>,
+}
+
+impl<S: Iterator<Item = u64>, const F: usize> MergeTree<S, F>
+where
+    FanIn<F>: SupportedFanIn,
+{
+    #[inline(always)]
+    pub fn new(mut srcs: [S; F]) -> Self {
+        let tree = LoserTree::new(std::array::from_fn(|i| srcs[i].next().unwrap_or(u64::MAX)));
+        Self { srcs, tree }
+    }
+
+    /// Removes and returns the smallest remaining element. Must be called at most as many times as there are
+    /// elements in the runs combined, and not again after it returns u64::MAX.
+    #[inline(always)]
+    pub fn pop(&mut self) -> u64 {
+        let i = self.tree.winner();
+        let result = self.tree.key(i);
+        self.tree.replace_winner(self.srcs[i].next().unwrap_or(u64::MAX));
+        result
     }
 
+    /// Returns the runs, e.g. to check them for errors after merging.
+    pub fn into_sources(self) -> [S; F] {
+        self.srcs
+    }
+}
+
+/// Merges the `F` sorted runs in `srcs` into `dst`, writing all of it.
+///
+/// `dst` must be exactly as long as the runs combined.
+#[inline(always)]
+fn merge<const F: usize>(srcs: [std::slice::Iter<u64>; F], dst: &mut [MaybeUninit<u64>])
+where
+    FanIn<F>: SupportedFanIn,
+{
+    let mut tree = MergeTree::new(srcs.map(|src| src.copied()));
+    let mut written = 0;
+    for d in dst.iter_mut() {
+        let key = tree.pop();
+        if key == u64::MAX {
+            break;
+        }
+        d.write(key);
+        written += 1;
+    }
+    // Everything left is u64::MAX; see `MergeTree`.
+    for d in &mut dst[written..] {
+        d.write(u64::MAX);
+    }
 }
 
+/// Counts the distinct values in the `F` sorted runs in `srcs`, which hold `len` elements combined, as they leave the
+/// tournament tree, without writing them anywhere.
+#[inline(always)]
+fn merge_count_unique<const F: usize>(srcs: [std::slice::Iter<u64>; F], len: usize) -> usize
+where
+    FanIn<F>: SupportedFanIn,
+{
+    if len == 0 {
+        return 0;
+    }
+    let mut tree = MergeTree::new(srcs.map(|src| src.copied()));
+    let mut prev = tree.pop();
+    let mut count = 1;
+    for _ in 1..len {
+        if prev == u64::MAX {
+            // Everything left is u64::MAX too; see `MergeTree`.
+            break;
+        }
+        let current = tree.pop();
+        count += (current != prev) as usize;
+        prev = current;
+    }
+    count
+}
+
+/// Sorts `data`, merging 256 sorted runs at a time.
 pub fn wide_merge_sort(data: &mut [u64]) {
     if data.len() <= 1024 {
-        data.sort_unstable();
+        // Just the base case, with its auxiliary buffer on the stack rather than allocated.
+        let mut aux = [MaybeUninit::uninit(); 1024];
+        small_sort::sort(data, &mut aux[..data.len()], false);
         return;
     }
     
     // Single allocation for auxiliary buffer
-    let mut aux = vec![0u64; data.len()];
-    wide_merge_sort_recursive(data, &mut aux, false);
+    let mut aux = uninit_words(data.len());
+    wide_merge_sort_recursive::<N>(data, &mut aux, false, 0);
+}
+
+/// Like `wide_merge_sort`, but uses the caller's `aux` as the auxiliary buffer, without allocating.
+///
+/// `aux` must be at least as long as `data`. It may be uninitialized, and its contents on entry are ignored, so it can
+/// be reused across calls.
+pub fn wide_merge_sort_with_scratch(data: &mut [u64], aux: &mut [MaybeUninit<u64>]) {
+    wide_merge_sort_with_fan_in::<N>(data, aux);
 }
 
-/// Recursively sorts the data using 256-way merge sort.
+/// Like `wide_merge_sort_with_scratch`, but merges `F` runs at a time rather than 256.
+///
+/// Larger fan-ins need fewer passes over the data, but a bigger tournament tree and one cache line per run in flight.
+pub fn wide_merge_sort_with_fan_in<const F: usize>(data: &mut [u64], aux: &mut [MaybeUninit<u64>])
+where
+    FanIn<F>: SupportedFanIn,
+{
+    wide_merge_sort_recursive::<F>(data, &mut aux[..data.len()], false, 0);
+}
+
+/// Like `wide_merge_sort` followed by counting unique values, but counts the distinct values as they leave the
+/// top-level merge, so the final merge writes nothing and the sorted array is never scanned.
+///
+/// Leaves `data` partially sorted: as the sorted runs that would have been merged.
+pub fn wide_merge_sort_and_count(data: &mut [u64]) -> usize {
+    wide_merge_sort_and_count_with_scratch(data, &mut uninit_words(data.len()))
+}
+
+/// Like `wide_merge_sort_and_count`, but uses the caller's `aux` as the auxiliary buffer, without allocating.
+///
+/// `aux` must be at least as long as `data`. It may be uninitialized, and its contents on entry are ignored, so it can
+/// be reused across calls.
+pub fn wide_merge_sort_and_count_with_scratch(data: &mut [u64], aux: &mut [MaybeUninit<u64>]) -> usize {
+    let len = data.len();
+    let aux = &mut aux[..len];
+    if len <= 1024 {
+        small_sort::sort(data, aux, false);
+        return count_unique_in_sorted(data);
+    }
+    {
+        let _phase = (len.div_ceil(N) <= 1024).then(|| phase_timing::phase("wide_merge_sort: base case sorts"));
+        for i in 0..N {
+            let chunk_range = (len * i) / N..(len * (i + 1)) / N;
+            wide_merge_sort_recursive::<N>(&mut data[chunk_range.clone()], &mut aux[chunk_range], false, 1);
+        }
+    }
+    let _phase = phase_timing::phase("wide_merge_sort: counting merge");
+    let srcs = std::array::from_fn(|i| data[(len * i) / N..(len * (i + 1)) / N].iter());
+    merge_count_unique::<N>(srcs, len)
+}
+
+/// Recursively sorts the data using `F`-way merge sort.
 /// 
-/// If write_to_aux is true, writes the result to aux. Otherwise, writes the result to data.
-fn wide_merge_sort_recursive(data: &mut [u64], aux: &mut [u64], write_to_aux: bool) {
+/// If write_to_aux is true, writes the result to aux. Otherwise, writes the result to data. `depth` is the recursion
+/// depth, used only for phase timing.
+fn wide_merge_sort_recursive<const F: usize>(
+    data: &mut [u64],
+    aux: &mut [MaybeUninit<u64>],
+    write_to_aux: bool,
+    depth: usize,
+) where
+    FanIn<F>: SupportedFanIn,
+{
     let len = data.len();
     
-    // Base case: use sort_unstable for small arrays
-    // Output: data (in-place sort)
+    // Base case: sorting network plus small merges for small arrays
     if len <= 1024 {
-        data.sort_unstable();
-        if write_to_aux {
-            aux.copy_from_slice(data);
-        }
+        small_sort::sort(data, aux, write_to_aux);
         return;
     }
 
-    // Recurse on chunks.
+    // Recurse on chunks. Only time the recursion when it bottoms out; deeper levels are timed by their merges.
+    let base_case_phase = (len.div_ceil(F) <= 1024).then(|| phase_timing::phase("wide_merge_sort: base case sorts"));
     let not_write_to_aux = !write_to_aux;
-    for i in 0..N {
-        let chunk_start = (len * i) / N;
-        let chunk_end = (len * (i + 1)) / N;
+    for i in 0..F {
+        let chunk_start = (len * i) / F;
+        let chunk_end = (len * (i + 1)) / F;
         let chunk_range = chunk_start..chunk_end;
-        wide_merge_sort_recursive(&mut data[chunk_range.clone()], &mut aux[chunk_range], not_write_to_aux);
+        wide_merge_sort_recursive::<F>(&mut data[chunk_range.clone()], &mut aux[chunk_range], not_write_to_aux, depth + 1);
     }
+    drop(base_case_phase);
     // Merge.
-    let (merge_src, merge_dst) = if write_to_aux {
+    let _phase = phase_timing::indexed_phase("wide_merge_sort: merge at depth", depth);
+    let (merge_src, merge_dst): (&[u64], _) = if write_to_aux {
         (data, aux)
     } else {
-        (aux, data)
+        // Safety: the recursive calls wrote their sorted chunks, and so all of `aux`. The merge writes to `data` only
+        // words it read from the runs.
+        (unsafe { aux.assume_init_ref() }, unsafe { as_uninit_mut(data) })
     };
     let srcs = std::array::from_fn(|i| {
-        let chunk_start = (len * i) / N;
-        let chunk_end = (len * (i + 1)) / N;
+        let chunk_start = (len * i) / F;
+        let chunk_end = (len * (i + 1)) / F;
         merge_src[chunk_start..chunk_end].iter()
     });
-    merge256(srcs, merge_dst)
+    merge::<F>(srcs, merge_dst)
+}
+
+/// Parallel version of `wide_merge_sort`, using the rayon thread pool.
+///
+/// Sorts the 256 top-level chunks in parallel, then splits the final 256-way merge into independent output ranges,
+/// each of which is merged on its own thread.
+pub fn par_wide_merge_sort(data: &mut [u64]) {
+    par_wide_merge_sort_with_scratch(data, &mut uninit_words(data.len()));
+}
+
+/// Like `par_wide_merge_sort`, but uses the caller's `aux` as the auxiliary buffer, without allocating.
+///
+/// `aux` must be at least as long as `data`. It may be uninitialized, and its contents on entry are ignored, so it can
+/// be reused across calls.
+pub fn par_wide_merge_sort_with_scratch(data: &mut [u64], aux: &mut [MaybeUninit<u64>]) {
+    let len = data.len();
+    if len <= N * 1024 {
+        wide_merge_sort_with_scratch(data, aux);
+        return;
+    }
+    let aux = &mut aux[..len];
+
+    // Sort the chunks in parallel, writing the sorted runs to aux.
+    {
+        let _phase = phase_timing::phase("par_wide_merge_sort: chunk sorts");
+        let mut chunks = Vec::with_capacity(N);
+        let (mut data_rest, mut aux_rest) = (&mut data[..], &mut aux[..]);
+        for i in 0..N {
+            let chunk_len = (len * (i + 1)) / N - (len * i) / N;
+            let (data_chunk, data_tail) = data_rest.split_at_mut(chunk_len);
+            let (aux_chunk, aux_tail) = aux_rest.split_at_mut(chunk_len);
+            chunks.push((data_chunk, aux_chunk));
+            (data_rest, aux_rest) = (data_tail, aux_tail);
+        }
+        chunks.into_par_iter().for_each(|(data_chunk, aux_chunk)| {
+            wide_merge_sort_recursive::<N>(data_chunk, aux_chunk, true, 1);
+        });
+    }
+
+    // Split the merge into parts of equal output size.
+    // Safety: the chunk sorts wrote their sorted runs, and so all of `aux`.
+    let aux = unsafe { aux.assume_init_ref() };
+    let runs: [&[u64]; N] = std::array::from_fn(|i| &aux[(len * i) / N..(len * (i + 1)) / N]);
+    let parts = rayon::current_num_threads() * MERGE_PARTS_PER_THREAD;
+    let splits: Vec<[usize; N]> = {
+        let _phase = phase_timing::phase("par_wide_merge_sort: splitter search");
+        (0..=parts).into_par_iter().map(|p| split_runs(&runs, (len * p) / parts)).collect()
+    };
+
+    // Merge each part into its own range of data, in parallel.
+    let _phase = phase_timing::phase("par_wide_merge_sort: merge");
+    let mut dsts = Vec::with_capacity(parts);
+    // Safety: the merges write to `data` only words they read from the runs.
+    let mut data_rest = unsafe { as_uninit_mut(data) };
+    for p in 0..parts {
+        let part_len = (len * (p + 1)) / parts - (len * p) / parts;
+        let (dst, tail) = data_rest.split_at_mut(part_len);
+        dsts.push(dst);
+        data_rest = tail;
+    }
+    dsts.into_par_iter().enumerate().for_each(|(p, dst)| {
+        let (lo, hi) = (&splits[p], &splits[p + 1]);
+        let srcs = std::array::from_fn(|i| runs[i][lo[i]..hi[i]].iter());
+        merge::<N>(srcs, dst);
+    });
+}
+
+/// Finds a position in each sorted run such that exactly `rank` elements in total lie before the positions, and no
+/// element before them is greater than any element after them.
+fn split_runs(runs: &[&[u64]; N], rank: usize) -> [usize; N] {
+    let count_le = |v: u64| runs.iter().map(|run| run.partition_point(|&x| x <= v)).sum::<usize>();
+    // Binary search for the smallest value v such that at least `rank` elements are <= v.
+    let (mut lo, mut hi) = (0u64, u64::MAX);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if count_le(mid) >= rank {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    let v = lo;
+    // Take everything less than v, then make up the difference from the elements equal to v.
+    let mut splits: [usize; N] = std::array::from_fn(|i| runs[i].partition_point(|&x| x < v));
+    let mut remaining = rank - splits.iter().sum::<usize>();
+    for (split, run) in splits.iter_mut().zip(runs) {
+        let equal = run[*split..].partition_point(|&x| x == v);
+        let take = equal.min(remaining);
+        *split += take;
+        remaining -= take;
+    }
+    splits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lengths around the 1024-word base case, and around 256 chunks of 1024 words, where a second level of merging
+    /// starts and the parallel sort stops deferring to the sequential one.
+    const LENGTHS: &[usize] = &[0, 1, 2, 1000, 1023, 1024, 1025, 2048, 5000, 262_143, 262_144, 262_145, 300_000];
+
+    /// `len` words from `pattern`, which is given each index and the length of the 256 top-level chunks.
+    fn words(len: usize, mut pattern: impl FnMut(usize, usize) -> u64) -> Vec<u64> {
+        let chunk_len = len.div_ceil(N).max(1);
+        (0..len).map(|i| pattern(i, chunk_len)).collect()
+    }
+
+    fn check(data: &[u64]) {
+        let mut expected = data.to_vec();
+        expected.sort_unstable();
+        let unique = count_unique_in_sorted(&expected);
+
+        let mut sorted = data.to_vec();
+        wide_merge_sort(&mut sorted);
+        assert_eq!(sorted, expected, "wide_merge_sort, len {}", data.len());
+
+        let mut sorted = data.to_vec();
+        par_wide_merge_sort(&mut sorted);
+        assert_eq!(sorted, expected, "par_wide_merge_sort, len {}", data.len());
+
+        let count = wide_merge_sort_and_count(&mut data.to_vec());
+        assert_eq!(count, unique, "wide_merge_sort_and_count, len {}", data.len());
+    }
+
+    type SortWithScratch = fn(&mut [u64], &mut [MaybeUninit<u64>]);
+
+    /// Checks every fan-in width of the tree, from 2 up to more runs than there are words.
+    fn check_fan_ins(data: &[u64]) {
+        let mut expected = data.to_vec();
+        expected.sort_unstable();
+        let mut aux = uninit_words(data.len());
+        let sorts: [SortWithScratch; 4] = [
+            wide_merge_sort_with_fan_in::<2>,
+            wide_merge_sort_with_fan_in::<16>,
+            wide_merge_sort_with_fan_in::<256>,
+            wide_merge_sort_with_fan_in::<4096>,
+        ];
+        for (i, sort) in sorts.iter().enumerate() {
+            let mut sorted = data.to_vec();
+            sort(&mut sorted, &mut aux);
+            assert_eq!(sorted, expected, "fan-in #{i}, len {}", data.len());
+        }
+    }
+
+    #[test]
+    fn all_max() {
+        for &len in LENGTHS {
+            let data = vec![u64::MAX; len];
+            check(&data);
+            if len <= 5000 {
+                check_fan_ins(&data);
+            }
+        }
+    }
+
+    #[test]
+    fn max_mixed_into_random() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        for &len in LENGTHS {
+            let data = words(len, |_, _| if rng.u8(..) < 32 { u64::MAX } else { rng.u64(..) });
+            check(&data);
+            if len <= 5000 {
+                check_fan_ins(&data);
+            }
+        }
+    }
+
+    #[test]
+    fn max_runs_next_to_live_runs() {
+        let mut rng = fastrand::Rng::with_seed(2);
+        for &len in LENGTHS {
+            // Every other chunk is all u64::MAX, so those runs tie with exhausted ones from the start.
+            let data =
+                words(len, |i, chunk_len| if (i / chunk_len).is_multiple_of(2) { u64::MAX } else { rng.u64(..) });
+            check(&data);
+            // A few live values, so most runs are exhausted while u64::MAX runs remain.
+            let data = words(len, |i, _| if i % 97 == 0 { rng.u64(..4) } else { u64::MAX });
+            check(&data);
+            // u64::MAX - 1 and u64::MAX, so live keys just below the sentinel compete with it.
+            let data = words(len, |_, _| u64::MAX - rng.u64(..2));
+            check(&data);
+            if len <= 5000 {
+                check_fan_ins(&data);
+            }
+        }
+    }
+
+    #[test]
+    fn split_runs_shares_out_repeated_values() {
+        let mut rng = fastrand::Rng::with_seed(3);
+        // Three values spread across all the runs, some of them empty, so nearly every rank lands inside a stretch of
+        // equal values that has to be shared out between runs.
+        let runs: Vec<Vec<u64>> = (0..N)
+            .map(|_| {
+                let mut run: Vec<u64> = (0..rng.usize(..8)).map(|_| [0, 7, u64::MAX][rng.usize(..3)]).collect();
+                run.sort_unstable();
+                run
+            })
+            .collect();
+        let runs: [&[u64]; N] = std::array::from_fn(|i| &runs[i][..]);
+        let len = runs.iter().map(|run| run.len()).sum::<usize>();
+
+        let mut prev = [0; N];
+        for rank in 0..=len {
+            let splits = split_runs(&runs, rank);
+            assert_eq!(splits.iter().sum::<usize>(), rank, "rank {rank}");
+            for (i, run) in runs.iter().enumerate() {
+                assert!(prev[i] <= splits[i] && splits[i] <= run.len(), "run {i}, rank {rank}");
+            }
+            let before = runs.iter().zip(&splits).filter_map(|(run, &split)| run[..split].last()).max();
+            let after = runs.iter().zip(&splits).filter_map(|(run, &split)| run.get(split)).min();
+            if let (Some(before), Some(after)) = (before, after) {
+                assert!(before <= after, "rank {rank}");
+            }
+            prev = splits;
+        }
+    }
 }
@@ -0,0 +1,156 @@
+//! Stable key-value version of `wide_merge_sort`: sorts records by a u64 key, keeping records with equal keys in
+//! input order. Useful for sort-merge joins and group-by, where the records carry more than the key.
+//!
+//! Same structure and `LoserTree` as `wide_merge_sort`, but the tree's keys can't rely on the u64::MAX sentinel: with
+//! payloads attached, an exhausted run winning a tie with a real u64::MAX key would emit the wrong record. So matches
+//! are decided by key, then by a rank that puts live runs before exhausted ones and otherwise lower runs first. That
+//! order is strict, which is also what makes the merge stable.
+
+use crate::phase_timing;
+use crate::uninit::as_uninit_mut;
+use crate::wide_merge_sort::{FanIn, LoserTree, SupportedFanIn, TreeKey};
+use std::mem::MaybeUninit;
+
+/// Fan-in of the merge: 256 runs merged at once.
+const N: usize = 256;
+
+/// Sorts `data` by `key`, stably.
+pub fn wide_merge_sort_by_key<T: Copy>(data: &mut [T], key: impl Fn(&T) -> u64) {
+    let mut aux = Box::new_uninit_slice(data.len());
+    wide_merge_sort_by_key_with_scratch(data, &mut aux, key);
+}
+
+/// Like `wide_merge_sort_by_key`, but uses the caller's `aux` as the auxiliary buffer, without allocating.
+///
+/// `aux` must be at least as long as `data`. It may be uninitialized, and its contents on entry are ignored, so it can
+/// be reused across calls.
+pub fn wide_merge_sort_by_key_with_scratch<T: Copy>(
+    data: &mut [T],
+    aux: &mut [MaybeUninit<T>],
+    key: impl Fn(&T) -> u64,
+) {
+    if data.len() <= 1024 {
+        data.sort_by_key(&key);
+        return;
+    }
+    let len = data.len();
+    wide_merge_sort_by_key_recursive::<T, N>(data, &mut aux[..len], &key, false, 0);
+}
+
+/// Recursively sorts the data by key using `F`-way merge sort.
+///
+/// If write_to_aux is true, writes the result to aux. Otherwise, writes the result to data. `depth` is the recursion
+/// depth, used only for phase timing.
+fn wide_merge_sort_by_key_recursive<T: Copy, const F: usize>(
+    data: &mut [T],
+    aux: &mut [MaybeUninit<T>],
+    key: &impl Fn(&T) -> u64,
+    write_to_aux: bool,
+    depth: usize,
+) where
+    FanIn<F>: SupportedFanIn,
+{
+    let len = data.len();
+
+    // Base case: the standard library's stable sort for small arrays
+    if len <= 1024 {
+        data.sort_by_key(key);
+        if write_to_aux {
+            aux.write_copy_of_slice(data);
+        }
+        return;
+    }
+
+    // Recurse on chunks. Only time the recursion when it bottoms out; deeper levels are timed by their merges.
+    let base_case_phase =
+        (len.div_ceil(F) <= 1024).then(|| phase_timing::phase("wide_merge_sort_by_key: base case sorts"));
+    for i in 0..F {
+        let chunk_range = (len * i) / F..(len * (i + 1)) / F;
+        wide_merge_sort_by_key_recursive::<T, F>(
+            &mut data[chunk_range.clone()],
+            &mut aux[chunk_range],
+            key,
+            !write_to_aux,
+            depth + 1,
+        );
+    }
+    drop(base_case_phase);
+    // Merge.
+    let _phase = phase_timing::indexed_phase("wide_merge_sort_by_key: merge at depth", depth);
+    let (merge_src, merge_dst): (&[T], _) = if write_to_aux {
+        (data, aux)
+    } else {
+        // Safety: the recursive calls wrote their sorted chunks, and so all of `aux`. The merge writes to `data` only
+        // records it read from the runs.
+        (unsafe { aux.assume_init_ref() }, unsafe { as_uninit_mut(data) })
+    };
+    let runs: [&[T]; F] = std::array::from_fn(|i| &merge_src[(len * i) / F..(len * (i + 1)) / F]);
+    let mut pos = [0usize; F];
+    let mut tree = LoserTree::new(std::array::from_fn(|i| RankedKey::of_head::<F, _>(runs[i].first(), i, key)));
+    for d in merge_dst {
+        let i = tree.winner();
+        debug_assert!(tree.key(i).rank < F, "popped more records than the runs hold");
+        d.write(runs[i][pos[i]]);
+        pos[i] += 1;
+        tree.replace_winner(RankedKey::of_head::<F, _>(runs[i].get(pos[i]), i, key));
+    }
+}
+
+/// The key of a run's head, with the run's rank to break ties.
+#[derive(Clone, Copy)]
+struct RankedKey {
+    /// The head's key, or u64::MAX if the run is exhausted.
+    key: u64,
+    /// The run's index, plus the fan-in once it is exhausted.
+    rank: usize,
+}
+
+impl RankedKey {
+    /// Returns the key of `head`, the head of run `run`, or of an exhausted run if it is `None`.
+    #[inline(always)]
+    fn of_head<const F: usize, T>(head: Option<&T>, run: usize, key: &impl Fn(&T) -> u64) -> Self {
+        match head {
+            Some(record) => Self { key: key(record), rank: run },
+            None => Self { key: u64::MAX, rank: run + F },
+        }
+    }
+}
+
+impl TreeKey for RankedKey {
+    /// The smaller key wins, then the smaller rank.
+    #[inline(always)]
+    fn beats(self, other: Self) -> bool {
+        // Bitwise rather than short-circuiting, so that this compiles to flag arithmetic rather than a second branch.
+        (self.key < other.key) | ((self.key == other.key) & (self.rank < other.rank))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lengths around the 1024-record base case, and past 256 chunks of 1024 records, where merges nest.
+    const LENGTHS: &[usize] = &[0, 1, 1000, 1024, 1025, 5000, 300_000];
+
+    /// Sorts `(key, original index)` records, so any reordering of equal keys shows up in the indices.
+    fn check(keys: &[u64]) {
+        let records: Vec<(u64, usize)> = keys.iter().copied().zip(0..).collect();
+        let mut expected = records.clone();
+        expected.sort_by_key(|&(key, _)| key);
+        let mut sorted = records;
+        wide_merge_sort_by_key(&mut sorted, |&(key, _)| key);
+        assert!(sorted == expected, "len {}", keys.len());
+    }
+
+    #[test]
+    fn stable_with_many_equal_keys() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        for &len in LENGTHS {
+            check(&(0..len).map(|_| rng.u64(..8)).collect::<Vec<_>>());
+            check(&vec![7; len]);
+            // Real u64::MAX keys tie with the key of exhausted runs.
+            check(&(0..len).map(|_| u64::MAX - rng.u64(..2)).collect::<Vec<_>>());
+            check(&(0..len).map(|_| rng.u64(..)).collect::<Vec<_>>());
+        }
+    }
+}
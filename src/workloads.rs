@@ -0,0 +1,60 @@
+//! Generators of the keys the benchmark runs on, shared with the tests and `hash_quality` so they all draw from the
+//! same distributions.
+
+/// Returns `len` words drawn from `num_words` random words, the i-th of which is drawn with probability proportional
+/// to 1 / i^`exponent`.
+pub fn zipf_keys(rng: &mut fastrand::Rng, len: usize, num_words: usize, exponent: f64) -> Vec<u64> {
+    let words: Vec<u64> = (0..num_words).map(|_| rng.u64(..)).collect();
+    let mut cumulative_weights = Vec::with_capacity(num_words);
+    let mut total = 0.0;
+    for i in 1..=num_words {
+        total += (i as f64).powf(-exponent);
+        cumulative_weights.push(total);
+    }
+    (0..len)
+        .map(|_| {
+            let x = rng.f64() * total;
+            let i = cumulative_weights.partition_point(|&weight| weight <= x);
+            words[i.min(num_words - 1)]
+        })
+        .collect()
+}
+
+/// Which bits of the keys `random_keys` returns carry their entropy.
+#[derive(Debug, Clone, Copy)]
+pub enum MaskStyle {
+    /// All the entropy is in the low bits. Friendly to most algorithms, even with Noop hashing.
+    LowBits,
+    /// All the entropy is in the high bits. Unfriendly to most hashing algorithms, which need entropy in the low bits.
+    /// Friendly enough for radix sort algorithms which are adaptive to where the entropy is.
+    HighBits,
+    /// The entropy is spread out over pairs of bits: each even bit is equal to the next odd bit. This tends to be
+    /// unfriendly to NoOp hashing both for hashing and radix sort algorithms.
+    SpreadOut2x,
+    /// All bits are used.
+    AllBits,
+}
+
+impl MaskStyle {
+    pub const ALL: [MaskStyle; 4] = [MaskStyle::LowBits, MaskStyle::HighBits, MaskStyle::SpreadOut2x, MaskStyle::AllBits];
+}
+
+/// Returns `len` random keys drawn from a domain of `2^lg_domain_size` values, laid out according to `mask_style`.
+pub fn random_keys(rng: &mut fastrand::Rng, len: usize, lg_domain_size: usize, mask_style: MaskStyle) -> Vec<u64> {
+    let mask = match mask_style {
+        MaskStyle::LowBits => (1u64 << lg_domain_size) - 1,
+        MaskStyle::HighBits => (1u64 << lg_domain_size).wrapping_neg(),
+        MaskStyle::SpreadOut2x => ((1u64 << (2 * lg_domain_size)) - 1) & 0x5555_5555_5555_5555,
+        MaskStyle::AllBits => u64::MAX,
+    };
+    (0..len)
+        .map(|_| {
+            let random = rng.u64(..);
+            let mut masked = random & mask;
+            if matches!(mask_style, MaskStyle::SpreadOut2x) {
+                masked = masked | (masked << 1);
+            }
+            masked
+        })
+        .collect()
+}